@@ -0,0 +1,112 @@
+//! Response body buffering for [`Link::next_body`](crate::Link::next_body)
+
+use std::{
+    error::Error as StdError,
+    future::poll_fn,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::ServiceResponse,
+    web::{Bytes, BytesMut},
+};
+
+/// Buffer the response body up to the given limit.
+///
+/// The body is always reattached to the returned response, and the
+/// buffered body is only returned if it was read in full.
+pub(crate) async fn buffer_body(
+    res: ServiceResponse,
+    limit: usize,
+) -> (ServiceResponse, Option<Bytes>) {
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let body = match body.size() {
+        BodySize::None => {
+            return (
+                ServiceResponse::new(req, res.set_body(body)),
+                Some(Bytes::new()),
+            );
+        }
+        BodySize::Sized(size) if size > limit as u64 => {
+            return (ServiceResponse::new(req, res.set_body(body)), None);
+        }
+        _ => body,
+    };
+    let mut body = match body.try_into_bytes() {
+        Ok(bytes) => {
+            let body = BoxBody::new(bytes.clone());
+            return (ServiceResponse::new(req, res.set_body(body)), Some(bytes));
+        }
+        Err(body) => body,
+    };
+
+    let mut buf = BytesMut::new();
+    let (body, bytes) = loop {
+        match poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await {
+            Some(Ok(chunk)) => {
+                buf.extend_from_slice(&chunk);
+                if buf.len() > limit {
+                    tracing::debug!("response body exceeded limit of {limit} bytes");
+                    let body = Prefixed {
+                        prefix: Some(buf.freeze()),
+                        rest: Some(body),
+                        error: None,
+                    };
+                    break (BoxBody::new(body), None);
+                }
+            }
+            Some(Err(err)) => {
+                let body = Prefixed {
+                    prefix: Some(buf.freeze()),
+                    rest: None,
+                    error: Some(err),
+                };
+                break (BoxBody::new(body), None);
+            }
+            None => {
+                let bytes = buf.freeze();
+                break (BoxBody::new(bytes.clone()), Some(bytes));
+            }
+        }
+    };
+    (ServiceResponse::new(req, res.set_body(body)), bytes)
+}
+
+/// Response body with a partially read prefix followed by the remaining
+/// body, or the error encountered while reading it.
+struct Prefixed {
+    prefix: Option<Bytes>,
+    rest: Option<BoxBody>,
+    error: Option<Box<dyn StdError>>,
+}
+
+impl MessageBody for Prefixed {
+    type Error = Box<dyn StdError>;
+
+    #[inline]
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(prefix) = this.prefix.take()
+            && !prefix.is_empty()
+        {
+            return Poll::Ready(Some(Ok(prefix)));
+        }
+        if let Some(err) = this.error.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
+        match this.rest.as_mut() {
+            Some(rest) => Pin::new(rest).poll_next(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Circuit breaker for [`Link::circuit_breaker`](crate::Link::circuit_breaker)
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Current state of a [`CircuitBreaker`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are passed to the link as normal.
+    Closed,
+    /// Requests skip the link entirely.
+    Open,
+    /// A limited number of probe requests are passed to the link
+    /// to determine if it has recovered.
+    HalfOpen,
+}
+
+/// Circuit breaker that stops calling a failing [`Link`](crate::Link).
+///
+/// A link failure is any service error or "5xx" response. Once the number
+/// of failures within the configured window reaches the threshold, the
+/// circuit opens and the link is skipped. After the open duration elapses
+/// the circuit becomes half-open and allows a number of probe requests,
+/// closing once all probes succeed or re-opening on any failure.
+///
+/// The breaker state is shared between clones, so a single breaker
+/// can be created outside the `HttpServer` factory to share state
+/// between workers and query it later.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+///
+/// use actix_web::web;
+/// use actix_chain::{CircuitBreaker, Link};
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// let breaker = CircuitBreaker::new(5, Duration::from_secs(10))
+///     .open_duration(Duration::from_secs(60))
+///     .half_open_probes(2);
+///
+/// Link::new(web::get().to(index))
+///     .circuit_breaker(breaker.clone());
+/// ```
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    threshold: usize,
+    window: Duration,
+    open_duration: Duration,
+    probes: u32,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+struct BreakerState {
+    state: CircuitState,
+    failures: VecDeque<Instant>,
+    opened_at: Instant,
+    probing: u32,
+    successes: u32,
+}
+
+impl CircuitBreaker {
+    /// Creates a new `CircuitBreaker` which opens after `threshold`
+    /// failures occur within the given `window`.
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            window,
+            open_duration: Duration::from_secs(30),
+            probes: 1,
+            state: Arc::new(Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                failures: VecDeque::new(),
+                opened_at: Instant::now(),
+                probing: 0,
+                successes: 0,
+            })),
+        }
+    }
+
+    /// Set how long the circuit stays open before allowing probes.
+    ///
+    /// Default is 30 seconds.
+    pub fn open_duration(mut self, duration: Duration) -> Self {
+        self.open_duration = duration;
+        self
+    }
+
+    /// Set the number of successful probes required to close
+    /// a half-open circuit.
+    ///
+    /// Default is 1.
+    pub fn half_open_probes(mut self, probes: u32) -> Self {
+        self.probes = probes.max(1);
+        self
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, BreakerState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Retrieve the current state of the circuit.
+    pub fn state(&self) -> CircuitState {
+        let state = self.lock();
+        match state.state {
+            CircuitState::Open if state.opened_at.elapsed() >= self.open_duration => {
+                CircuitState::HalfOpen
+            }
+            state => state,
+        }
+    }
+
+    /// Check if the circuit is open and the link should be skipped
+    #[inline]
+    pub(crate) fn is_open(&self) -> bool {
+        self.state() == CircuitState::Open
+    }
+
+    /// Request permission to call the link
+    pub(crate) fn acquire(&self) -> Option<Permit> {
+        let mut state = self.lock();
+        if state.state == CircuitState::Open {
+            if state.opened_at.elapsed() < self.open_duration {
+                return None;
+            }
+            tracing::info!("circuit breaker half-open");
+            state.state = CircuitState::HalfOpen;
+            state.probing = 0;
+            state.successes = 0;
+        }
+        let probe = state.state == CircuitState::HalfOpen;
+        if probe {
+            if state.probing + state.successes >= self.probes {
+                return None;
+            }
+            state.probing += 1;
+        }
+        Some(Permit {
+            breaker: self.clone(),
+            probe,
+            done: false,
+        })
+    }
+
+    fn open(&self, state: &mut BreakerState) {
+        tracing::warn!(
+            "circuit breaker opened for {:?} after {} failures",
+            self.open_duration,
+            state.failures.len()
+        );
+        state.state = CircuitState::Open;
+        state.opened_at = Instant::now();
+        state.failures.clear();
+        state.probing = 0;
+        state.successes = 0;
+    }
+
+    fn record(&self, probe: bool, success: Option<bool>) {
+        let mut state = self.lock();
+        match state.state {
+            CircuitState::Closed if success == Some(false) => {
+                let now = Instant::now();
+                state.failures.push_back(now);
+                while let Some(first) = state.failures.front()
+                    && now.duration_since(*first) > self.window
+                {
+                    state.failures.pop_front();
+                }
+                if state.failures.len() >= self.threshold {
+                    self.open(&mut state);
+                }
+            }
+            CircuitState::HalfOpen if probe => {
+                state.probing = state.probing.saturating_sub(1);
+                match success {
+                    Some(false) => self.open(&mut state),
+                    Some(true) => {
+                        state.successes += 1;
+                        if state.successes >= self.probes {
+                            tracing::info!("circuit breaker closed");
+                            state.state = CircuitState::Closed;
+                            state.failures.clear();
+                        }
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Permission to call a link guarded by a [`CircuitBreaker`].
+///
+/// Dropping the permit without recording a result releases
+/// any reserved half-open probe.
+pub(crate) struct Permit {
+    breaker: CircuitBreaker,
+    probe: bool,
+    done: bool,
+}
+
+impl Permit {
+    /// Record the result of the link call
+    pub(crate) fn record(mut self, success: bool) {
+        self.done = true;
+        self.breaker.record(self.probe, Some(success));
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.done {
+            self.breaker.record(self.probe, None);
+        }
+    }
+}
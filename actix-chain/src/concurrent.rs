@@ -0,0 +1,269 @@
+//! Concurrent link execution for [`Execution::Parallel`](crate::Execution::Parallel)
+//! and [`Execution::Hedged`](crate::Execution::Hedged)
+
+use std::{
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    HttpRequest,
+    dev::{Path, ServiceRequest, ServiceResponse, Url},
+    error::Error,
+    http::Uri,
+    rt::time::{Sleep, sleep},
+};
+use futures_core::future::LocalBoxFuture;
+
+use crate::{
+    copy::RequestCopy,
+    factory::Exhausted,
+    link::{LinkInner, Outcome},
+    payload::PayloadRef,
+    trace::ChainTrace,
+};
+
+/// State of a link started by [`Concurrent`]
+enum Branch<'a> {
+    /// Link service is still running
+    Running(LocalBoxFuture<'a, Result<Outcome, Error>>, Instant),
+    /// Link accepted the request, and its response is held until every
+    /// earlier link has forwarded the request
+    Accepted(ServiceResponse, usize),
+    /// Link forwarded the request, failed or was cancelled
+    Forwarded,
+}
+
+/// Future calling the matching links of a chain concurrently.
+///
+/// The first link receives the original request while every other link
+/// receives its own copy, so prefixes are stripped from each request
+/// independently. Link errors are treated like rejected responses.
+///
+/// Links are served in order: a response accepted by a link is only
+/// returned once every earlier link has forwarded the request, and any
+/// later links still running are cancelled. When every link forwards the
+/// request, the last link's response is returned according to the chain's
+/// [`Exhausted`] policy, otherwise the future resolves to `None` and the
+/// request is recovered with [`Concurrent::into_request`].
+pub(crate) struct Concurrent<'a> {
+    links: Vec<(usize, &'a LinkInner)>,
+    delay: Option<Duration>,
+    exhausted: Exhausted,
+    addr: String,
+    trace: &'a mut ChainTrace,
+    template: &'a RequestCopy,
+    buf: &'a PayloadRef,
+    /// Original request, until it is sent to the first link
+    original: Option<ServiceRequest>,
+    /// Original URI and match info replaced by the first link's prefix
+    restore: Option<(Uri, Path<Url>)>,
+    /// Original request once forwarded by the first link
+    spare: Option<HttpRequest>,
+    branches: Vec<Branch<'a>>,
+    timer: Option<Pin<Box<Sleep>>>,
+    last: Option<Result<(ServiceResponse, usize), Error>>,
+}
+
+impl<'a> Concurrent<'a> {
+    /// Prepare to call the links, starting each link after the `delay`
+    /// if specified, or all at once otherwise.
+    pub(crate) fn new(
+        req: ServiceRequest,
+        links: Vec<(usize, &'a LinkInner)>,
+        delay: Option<Duration>,
+        exhausted: Exhausted,
+        trace: &'a mut ChainTrace,
+        template: &'a RequestCopy,
+        buf: &'a PayloadRef,
+    ) -> Self {
+        let addr = req
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        Self {
+            branches: Vec::with_capacity(links.len()),
+            links,
+            delay,
+            exhausted,
+            addr,
+            trace,
+            template,
+            buf,
+            original: Some(req),
+            restore: None,
+            spare: None,
+            timer: None,
+            last: None,
+        }
+    }
+
+    /// Check if the next link should be started
+    fn due(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.branches.len() == self.links.len() {
+            return false;
+        }
+        let mut running = false;
+        for branch in self.branches.iter() {
+            match branch {
+                Branch::Running(..) => running = true,
+                // later links can no longer be served
+                Branch::Accepted(..) => return false,
+                Branch::Forwarded => {}
+            }
+        }
+        match self.delay {
+            None => true,
+            Some(_) if !running => true,
+            Some(_) => self
+                .timer
+                .as_mut()
+                .is_some_and(|timer| timer.as_mut().poll(cx).is_ready()),
+        }
+    }
+
+    /// Start the next link with the original request, or a copy of it
+    fn start(&mut self) {
+        let (_, link) = self.links[self.branches.len()];
+        tracing::debug!("{} calling link {link}", self.addr);
+        let call: LocalBoxFuture<'a, _> = match self.original.take() {
+            Some(mut req) => {
+                self.restore = link.apply(&mut req);
+                Box::pin(link.call_next(req, self.buf))
+            }
+            None => {
+                let (template, buf) = (self.template, self.buf);
+                Box::pin(async move {
+                    let mut req = template.build().await?;
+                    link.apply(&mut req);
+                    req.set_payload(buf.payload());
+                    link.call_next(req, buf).await
+                })
+            }
+        };
+        self.branches.push(Branch::Running(call, Instant::now()));
+        self.timer = self.delay.map(|delay| Box::pin(sleep(delay)));
+    }
+
+    /// Record the result of a finished link
+    fn settle(
+        &mut self,
+        i: usize,
+        result: Result<Outcome, Error>,
+        elapsed: Duration,
+    ) -> Branch<'a> {
+        let (_, link) = self.links[i];
+        link.trace(self.trace, &result, elapsed);
+        let pos = self.trace.attempts.len() - 1;
+        let is_last = i + 1 == self.links.len() && self.exhausted == Exhausted::LastResponse;
+        match result {
+            Ok(Outcome::Response(res)) => {
+                tracing::debug!("{} link {link} response={:?}", self.addr, res.status());
+                return Branch::Accepted(res, pos);
+            }
+            Ok(Outcome::Rejected(res)) => {
+                tracing::debug!("{} link {link} response={:?}", self.addr, res.status());
+                match is_last {
+                    true => self.last = Some(Ok((res, pos))),
+                    false if i == 0 => self.spare = Some(res.into_parts().0),
+                    false => {}
+                }
+            }
+            Ok(Outcome::Next(http_req)) => {
+                tracing::debug!("{} link {link} failed, continuing", self.addr);
+                if i == 0 {
+                    self.spare = Some(http_req);
+                }
+            }
+            Err(err) => {
+                tracing::debug!("{} link {link} error={err:?}, continuing", self.addr);
+                if is_last {
+                    self.last = Some(Err(err));
+                }
+            }
+        }
+        Branch::Forwarded
+    }
+
+    /// Recover the original request for the chain's default service,
+    /// or build a new copy if the first link consumed it
+    pub(crate) async fn into_request(self) -> Result<ServiceRequest, Error> {
+        if let Some(req) = self.original {
+            return Ok(req);
+        }
+        let Some(http_req) = self.spare else {
+            let mut req = self.template.build().await?;
+            req.set_payload(self.buf.payload());
+            return Ok(req);
+        };
+        let mut req = ServiceRequest::from_parts(http_req, self.buf.payload());
+        if let Some((uri, info)) = self.restore {
+            req.head_mut().uri = uri;
+            *req.match_info_mut() = info;
+        }
+        Ok(req)
+    }
+}
+
+impl Future for Concurrent<'_> {
+    type Output = Option<Result<ServiceResponse, Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            while this.due(cx) {
+                this.start();
+            }
+
+            // drive running links
+            let mut settled = false;
+            for i in 0..this.branches.len() {
+                let Branch::Running(call, start) = &mut this.branches[i] else {
+                    continue;
+                };
+                let Poll::Ready(result) = call.as_mut().poll(cx) else {
+                    continue;
+                };
+                let elapsed = start.elapsed();
+                let branch = this.settle(i, result, elapsed);
+                if let Branch::Accepted(..) = branch {
+                    // cancel later links which can no longer be served
+                    this.branches[i + 1..]
+                        .iter_mut()
+                        .for_each(|later| *later = Branch::Forwarded);
+                }
+                this.branches[i] = branch;
+                settled = true;
+            }
+
+            // serve the earliest link once every link before it forwarded
+            let first = this
+                .branches
+                .iter()
+                .position(|branch| !matches!(branch, Branch::Forwarded));
+            if let Some(i) = first
+                && matches!(this.branches[i], Branch::Accepted(..))
+                && let Branch::Accepted(res, pos) =
+                    mem::replace(&mut this.branches[i], Branch::Forwarded)
+            {
+                this.trace.served = Some(pos);
+                return Poll::Ready(Some(Ok(res)));
+            }
+            if first.is_none() && this.branches.len() == this.links.len() {
+                let res = this.last.take().map(|last| {
+                    last.map(|(res, pos)| {
+                        this.trace.served = Some(pos);
+                        res
+                    })
+                });
+                return Poll::Ready(res);
+            }
+            if !settled {
+                return Poll::Pending;
+            }
+        }
+    }
+}
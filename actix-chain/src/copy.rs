@@ -0,0 +1,115 @@
+//! Detached request copies for concurrent links and mirrors
+//!
+//! Actix-Web has no public constructor for [`HttpRequest`], so copies are
+//! produced by dispatching the request head through an empty [`App`]
+//! initialized with the original request's [`AppConfig`]. Its default
+//! service responds with the [`HttpRequest`] it was given, which becomes
+//! the copy once the response completes.
+//!
+//! A copy carries the method, URI, version, headers, peer address, match
+//! info and app config of the original request, as well as the data
+//! registered with [`Chain::app_data`](crate::Chain::app_data). Actix-Web
+//! does not expose the app data of a request, so data registered on the
+//! `App` or an enclosing scope is not available to the copy.
+
+use std::{cell::RefCell, future::ready, net::SocketAddr, rc::Rc};
+
+use actix_http::Request;
+use actix_service::{IntoServiceFactory, Service, ServiceFactory, boxed, fn_service};
+use actix_web::{
+    App, HttpRequest, HttpResponse,
+    dev::{AppConfig, Extensions, Path, ServiceRequest, ServiceResponse, Url},
+    error::{Error, ErrorInternalServerError},
+    http::{Method, Uri, Version, header::HeaderMap},
+};
+
+type CopyService = boxed::BoxService<Request, ServiceResponse, Error>;
+
+thread_local! {
+    /// Copying applications built for every distinct app config seen by the worker
+    static COPIERS: RefCell<Vec<(AppConfig, Rc<CopyService>)>> = const { RefCell::new(Vec::new()) };
+}
+
+#[inline]
+fn same_config(a: &AppConfig, b: &AppConfig) -> bool {
+    a.secure() == b.secure() && a.host() == b.host() && a.local_addr() == b.local_addr()
+}
+
+/// Retrieve the copying application for the given config, building it if required
+async fn copier(config: &AppConfig) -> Result<Rc<CopyService>, Error> {
+    let cached = COPIERS.with_borrow(|copiers| {
+        copiers
+            .iter()
+            .find(|(c, _)| same_config(c, config))
+            .map(|(_, copier)| Rc::clone(copier))
+    });
+    if let Some(copier) = cached {
+        return Ok(copier);
+    }
+    let app = App::new().default_service(fn_service(|req: ServiceRequest| {
+        ready(Ok(req.into_response(HttpResponse::NoContent().finish())))
+    }));
+    let service = app
+        .into_factory()
+        .new_service(config.clone())
+        .await
+        .map_err(|_| ErrorInternalServerError("failed to initialize request copier"))?;
+    let copier = Rc::new(boxed::service(service));
+    COPIERS.with_borrow_mut(|copiers| copiers.push((config.clone(), Rc::clone(&copier))));
+    Ok(copier)
+}
+
+/// Snapshot of a request used to build detached copies of it.
+///
+/// See the module documentation for the details that are copied.
+pub(crate) struct RequestCopy {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    peer_addr: Option<SocketAddr>,
+    match_info: Path<Url>,
+    config: AppConfig,
+    data: Option<Rc<Extensions>>,
+}
+
+impl RequestCopy {
+    pub(crate) fn new(req: &HttpRequest, data: Option<&Rc<Extensions>>) -> Self {
+        Self {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            headers: req.headers().clone(),
+            peer_addr: req.peer_addr(),
+            match_info: req.match_info().clone(),
+            config: req.app_config().clone(),
+            data: data.cloned(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn path(&self) -> &str {
+        self.uri.path()
+    }
+
+    /// Build a new request without a payload that does not share its
+    /// state with the original request.
+    pub(crate) async fn build(&self) -> Result<ServiceRequest, Error> {
+        let mut head = Request::new();
+        let copy = head.head_mut();
+        copy.method = self.method.clone();
+        copy.uri = self.uri.clone();
+        copy.version = self.version;
+        copy.headers = self.headers.clone();
+        copy.peer_addr = self.peer_addr;
+
+        let copier = copier(&self.config).await?;
+        let (http_req, _) = copier.call(head).await?.into_parts();
+        let mut req = ServiceRequest::from_request(http_req);
+        *req.match_info_mut() = self.match_info.clone();
+        if let Some(data) = self.data.as_ref() {
+            req.add_data_container(Rc::clone(data));
+        }
+        Ok(req)
+    }
+}
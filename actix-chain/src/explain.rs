@@ -0,0 +1,125 @@
+//! Dry-run routing reports for [`Chain::explain`](crate::Chain::explain)
+
+use std::{fmt, rc::Rc};
+
+use actix_web::http::Uri;
+
+/// Routing details of a single [`Link`](crate::Link) for a request.
+///
+/// See [`Chain::explain`](crate::Chain::explain) for more details.
+#[derive(Clone, Debug)]
+pub struct LinkExplain {
+    /// Position of the link within the chain.
+    pub index: usize,
+    /// Name assigned with [`Link::name`](crate::Link::name).
+    pub name: Option<Rc<str>>,
+    /// Prefix assigned with [`Link::prefix`](crate::Link::prefix).
+    pub prefix: String,
+    /// Whether the request path matched the link prefix.
+    pub prefix_matched: bool,
+    /// Whether the request passed all of the link guards.
+    pub guards_passed: bool,
+    /// URI passed to the link service, or `None` if the prefix did not match.
+    pub uri: Option<Uri>,
+    /// Parameters captured by the link prefix.
+    pub params: Vec<(String, String)>,
+    /// Report of the nested chain when the link was created with
+    /// `Link::from(Chain)` and the link matched.
+    pub nested: Option<ChainExplain>,
+}
+
+impl LinkExplain {
+    /// Check if the link would be called for the request.
+    #[inline]
+    pub fn matched(&self) -> bool {
+        self.prefix_matched && self.guards_passed
+    }
+}
+
+/// Report of how a [`Chain`](crate::Chain) routes a request.
+///
+/// Created with [`Chain::explain`](crate::Chain::explain) without calling
+/// any of the link services.
+///
+/// # Examples
+/// ```
+/// use actix_web::{test::TestRequest, web};
+/// use actix_chain::{Chain, Link};
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// let chain = Chain::default()
+///     .link(Link::new(web::get().to(index)).prefix("/api").name("api"))
+///     .link(Link::new(web::get().to(index)).name("fallback"));
+///
+/// let req = TestRequest::with_uri("/api/users").to_srv_request();
+/// let explain = chain.explain(&req);
+/// assert_eq!(explain.route(), vec!["api"]);
+/// println!("{explain}");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ChainExplain {
+    /// Reports for every link in the order they are tried.
+    pub links: Vec<LinkExplain>,
+}
+
+impl ChainExplain {
+    /// Retrieve the first link that would be called for the request.
+    pub fn first_match(&self) -> Option<&LinkExplain> {
+        self.links.iter().find(|link| link.matched())
+    }
+
+    /// List the first matching link at every level of nested chains.
+    ///
+    /// Links are listed by name if assigned, or by index otherwise.
+    pub fn route(&self) -> Vec<String> {
+        let mut route = vec![];
+        let mut chain = Some(self);
+        while let Some(link) = chain.and_then(|chain| chain.first_match()) {
+            route.push(link.to_string());
+            chain = link.nested.as_ref();
+        }
+        route
+    }
+
+    fn fmt_depth(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        for link in self.links.iter() {
+            let indent = depth * 2;
+            write!(
+                f,
+                "{:indent$}{link} prefix={:?} prefix_matched={} guards_passed={}",
+                "", link.prefix, link.prefix_matched, link.guards_passed
+            )?;
+            if let Some(uri) = link.uri.as_ref() {
+                write!(f, " uri={uri}")?;
+            }
+            for (name, value) in link.params.iter() {
+                write!(f, " {name}={value:?}")?;
+            }
+            writeln!(f)?;
+            if let Some(nested) = link.nested.as_ref() {
+                nested.fmt_depth(f, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LinkExplain {
+    /// Format the link name if assigned, or its index otherwise
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name.as_ref() {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "{}", self.index),
+        }
+    }
+}
+
+impl fmt::Display for ChainExplain {
+    /// Format the report as an indented tree with one link per line
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_depth(f, 0)
+    }
+}
@@ -1,17 +1,70 @@
-use std::rc::Rc;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::Duration,
+};
 
-use actix_service::{ServiceFactory, Transform};
+use actix_service::{IntoServiceFactory, ServiceFactory, Transform};
 use actix_web::{
     Error,
     body::MessageBody,
-    dev::{AppService, HttpServiceFactory, ResourceDef, ServiceRequest, ServiceResponse},
+    dev::{
+        AppService, Extensions, HttpServiceFactory, ResourceDef, ServiceRequest, ServiceResponse,
+    },
     guard::Guard,
+    http::{Uri, header::HeaderName},
 };
 use futures_core::future::LocalBoxFuture;
 
-use crate::{link::Link, next::Next, service::HttpService, wrap::Wrappable};
+use crate::{
+    explain::ChainExplain,
+    handle::{ChainHandle, DynamicLinks},
+    link::{Link, box_factory},
+    mirror::Mirror,
+    next::Next,
+    select::Selection,
+    service::{HttpNewService, HttpService},
+    wrap::Wrappable,
+};
+
+use super::{
+    select::Selector,
+    service::{ChainInner, ChainService},
+};
+
+/// Inserts a copy of the data registered with [`Chain::app_data`]
+type DataFactory = Rc<dyn Fn(&mut Extensions)>;
 
-use super::service::{ChainInner, ChainService};
+/// Strategy used to execute the [`Link`] instances of a [`Chain`].
+///
+/// See [`Chain::execution`] for more details.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Execution {
+    /// Call each link one after another, only calling the next link
+    /// once the previous one has forwarded the request.
+    #[default]
+    Sequential,
+    /// Call all matching links concurrently.
+    Parallel,
+    /// Call the first matching link, and start each following link
+    /// after the given delay, or as soon as every running link has
+    /// forwarded the request.
+    Hedged(Duration),
+}
+
+/// Response returned when every [`Link`] of a [`Chain`] forwards the request.
+///
+/// See [`Chain::on_exhausted`] for more details.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Exhausted {
+    /// Return the response of the last link, even when it matches
+    /// the link's [`Next`] criteria.
+    #[default]
+    LastResponse,
+    /// Discard the last link's response and call the chain's
+    /// default service instead.
+    Default,
+}
 
 /// Actix-Web service chaining service.
 ///
@@ -51,7 +104,16 @@ pub struct Chain {
     pub(crate) links: Vec<Link>,
     pub(crate) guards: Vec<Rc<dyn Guard>>,
     pub(crate) next: Vec<Rc<dyn Next>>, // For Into<Link> only
-    body_buffer_size: usize,
+    pub(crate) body_buffer_size: usize,
+    pub(crate) body_file_size: usize,
+    pub(crate) execution: Execution,
+    pub(crate) selection: Selection,
+    pub(crate) mirrors: Vec<Mirror>,
+    pub(crate) default: Option<Rc<HttpNewService>>,
+    pub(crate) exhausted: Exhausted,
+    pub(crate) debug_header: Option<HeaderName>,
+    pub(crate) handle: Option<(usize, ChainHandle)>,
+    pub(crate) data: Vec<DataFactory>,
 }
 
 impl Chain {
@@ -66,6 +128,15 @@ impl Chain {
             guards: Vec::new(),
             next: Vec::new(),
             body_buffer_size: 32 * 1024, // 32 kb default
+            body_file_size: 0,
+            execution: Execution::default(),
+            selection: Selection::default(),
+            mirrors: Vec::new(),
+            default: None,
+            exhausted: Exhausted::default(),
+            debug_header: None,
+            handle: None,
+            data: Vec::new(),
         }
     }
 
@@ -133,6 +204,295 @@ impl Chain {
         self.wrap_with(middleware)
     }
 
+    /// Set the maximum request body size kept in memory.
+    ///
+    /// Request bodies are buffered so they can be replayed when a
+    /// link falls through to the next. Bodies larger than this limit
+    /// are spilled to a temporary file up to [`Chain::body_file_size`],
+    /// and fail with a payload overflow error past that.
+    ///
+    /// Default is 32KiB.
+    pub fn body_buffer_size(mut self, size: usize) -> Self {
+        self.body_buffer_size = size;
+        self
+    }
+
+    /// Set the maximum request body size spilled to disk once the
+    /// in-memory [`Chain::body_buffer_size`] is exceeded.
+    ///
+    /// Spilled data is stored in a temporary file within
+    /// [`std::env::temp_dir`], readable only by the current user, and
+    /// removed once the request completes. File reads and writes run on
+    /// the blocking thread pool rather than the worker thread.
+    ///
+    /// Default is 0, which disables spilling to disk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use actix_web::web;
+    /// use actix_chain::{Chain, Link};
+    ///
+    /// let chain = Chain::default()
+    ///     .body_buffer_size(64 * 1024)
+    ///     .body_file_size(16 * 1024 * 1024)
+    ///     .link(Link::new(web::post().to(|| async { "ok" })));
+    /// ```
+    pub fn body_file_size(mut self, size: usize) -> Self {
+        self.body_file_size = size;
+        self
+    }
+
+    /// Configure how links within the chain are executed.
+    ///
+    /// With [`Execution::Parallel`] and [`Execution::Hedged`] multiple
+    /// links may be running at once. Responses are served in the order of
+    /// the links: a response that passes the [`Next`] criteria is returned
+    /// once every earlier link has forwarded the request, and any later
+    /// links still running are cancelled. The request body is buffered and
+    /// replayed to every running link.
+    ///
+    /// Only the first running link receives the original request. Every
+    /// other link receives its own copy of the request, which only carries
+    /// the app data registered with [`Chain::app_data`]. Link errors are
+    /// treated like rejected responses, so the remaining links keep running.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use actix_web::web;
+    /// use actix_chain::{Chain, Execution, Link};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// let chain = Chain::default()
+    ///     .execution(Execution::Hedged(Duration::from_millis(50)))
+    ///     .link(Link::new(web::get().to(index)))
+    ///     .link(Link::new(web::get().to(index)));
+    /// ```
+    pub fn execution(mut self, execution: Execution) -> Self {
+        self.execution = execution;
+        self
+    }
+
+    /// Configure which matching link is called first.
+    ///
+    /// The default [`Selection::Sequential`] strategy always calls links in
+    /// the order they were added. Other strategies allow the chain to act as
+    /// a simple load balancer, while the remaining links still act as fallbacks.
+    ///
+    /// See [`Link::weight`] to adjust how often each link is chosen.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use actix_web::web;
+    /// use actix_chain::{Chain, Link, Selection};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// let chain = Chain::default()
+    ///     .selection(Selection::StickyCookie("session".to_owned()))
+    ///     .link(Link::new(web::get().to(index)))
+    ///     .link(Link::new(web::get().to(index)));
+    /// ```
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Add a [`Mirror`] which receives a copy of requests sent to the chain.
+    ///
+    /// Mirrors are useful to test a new service against real traffic
+    /// without affecting the responses returned to clients.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use actix_web::web;
+    /// use actix_chain::{Chain, Link, Mirror};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// let chain = Chain::default()
+    ///     .link(Link::new(web::get().to(index)))
+    ///     .mirror(Mirror::new(web::get().to(index)).sample(0.5));
+    /// ```
+    pub fn mirror(mut self, mirror: Mirror) -> Self {
+        self.mirrors.push(mirror);
+        self
+    }
+
+    /// Add application data available to the chain's links.
+    ///
+    /// The data is added to every request handled by the chain, in the
+    /// same way as [`actix_web::Scope::app_data`]. Concurrent links and
+    /// mirrors receive copies of the request which do not carry the app
+    /// data of the `App` or any enclosing scope, so data extracted by
+    /// their services must be registered on the chain instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use actix_web::web;
+    /// use actix_chain::{Chain, Execution, Link};
+    ///
+    /// async fn index(name: web::Data<String>) -> String {
+    ///     format!("Hello {}!", name.as_str())
+    /// }
+    ///
+    /// let chain = Chain::default()
+    ///     .execution(Execution::Parallel)
+    ///     .app_data(web::Data::new("world".to_owned()))
+    ///     .link(Link::new(web::get().to(index)))
+    ///     .link(Link::new(web::get().to(index)));
+    /// ```
+    pub fn app_data<U: Clone + 'static>(mut self, data: U) -> Self {
+        self.data.push(Rc::new(move |extensions: &mut Extensions| {
+            extensions.insert(data.clone());
+        }));
+        self
+    }
+
+    /// Default service that is invoked when no matching link is found,
+    /// or when every link forwards the request.
+    ///
+    /// Defaults to a plain-text "404 Not Found" response.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use actix_web::{HttpResponse, web};
+    /// use actix_chain::{Chain, Link};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// let chain = Chain::default()
+    ///     .link(Link::new(web::get().to(index)).prefix("/index"))
+    ///     .default_service(web::to(|| HttpResponse::NotFound()));
+    /// ```
+    pub fn default_service<F, U>(mut self, service: F) -> Self
+    where
+        F: IntoServiceFactory<U, ServiceRequest>,
+        U: ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse, Error = Error>
+            + 'static,
+    {
+        self.default = Some(box_factory(service));
+        self
+    }
+
+    /// Configure the response returned when the last link's response
+    /// matches its [`Next`] criteria.
+    ///
+    /// The default [`Exhausted::LastResponse`] returns the last link's
+    /// response as is, while [`Exhausted::Default`] calls the chain's
+    /// [`Chain::default_service`] instead.
+    pub fn on_exhausted(mut self, exhausted: Exhausted) -> Self {
+        self.exhausted = exhausted;
+        self
+    }
+
+    /// Echo the [`ChainTrace`](crate::ChainTrace) of each request in the
+    /// specified response header.
+    ///
+    /// The trace is always available within the response extensions,
+    /// but is only added as a header when configured.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use actix_web::{http::header::HeaderName, web};
+    /// use actix_chain::{Chain, Link};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// let chain = Chain::default()
+    ///     .debug_header(HeaderName::from_static("x-chain-link"))
+    ///     .link(Link::new(web::get().to(index)).name("index"));
+    /// ```
+    pub fn debug_header(mut self, name: HeaderName) -> Self {
+        self.debug_header = Some(name);
+        self
+    }
+
+    /// Attach a [`ChainHandle`] used to reconfigure the chain at runtime.
+    ///
+    /// The links managed by the handle are placed after any links already
+    /// added to the chain, and before any links added afterwards. A chain
+    /// with a handle may be constructed without any other links.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use actix_web::web;
+    /// use actix_chain::{Chain, ChainHandle, Link};
+    ///
+    /// async fn unavailable() -> &'static str {
+    ///     "No backends available"
+    /// }
+    ///
+    /// let handle = ChainHandle::default();
+    /// let chain = Chain::default()
+    ///     .handle(handle.clone())
+    ///     .link(Link::new(web::get().to(unavailable)));
+    /// ```
+    pub fn handle(mut self, handle: ChainHandle) -> Self {
+        self.handle = Some((self.links.len(), handle));
+        self
+    }
+
+    /// Report which links would handle the request without calling
+    /// any of their services.
+    ///
+    /// Each link reports whether its prefix matched, whether its guards
+    /// passed, and the URI its service would receive. Links created from a
+    /// nested chain with `Link::from(Chain)` are expanded recursively, and
+    /// links managed by a [`ChainHandle`] are included as currently
+    /// configured.
+    ///
+    /// Links are reported in their configured order regardless of the
+    /// chain's [`Selection`] strategy, and circuit breakers are ignored.
+    ///
+    /// See [`ChainExplain`] for an example.
+    pub fn explain(&self, req: &ServiceRequest) -> ChainExplain {
+        self.explain_uri(req, req.uri())
+    }
+
+    /// Report which links would handle the request if it were sent to `uri`
+    pub(crate) fn explain_uri(&self, req: &ServiceRequest, uri: &Uri) -> ChainExplain {
+        let mut links = self.links.clone();
+        if let Some((position, handle)) = self.handle.as_ref() {
+            let (_, builders) = handle.snapshot();
+            let dynamic = builders.into_iter().map(|(id, build)| {
+                let link = build();
+                match link.name.is_some() {
+                    true => link,
+                    false => link.name(id),
+                }
+            });
+            links.splice(*position..*position, dynamic);
+        }
+        ChainExplain {
+            links: links
+                .iter()
+                .enumerate()
+                .map(|(index, link)| link.explain(index, req, uri))
+                .collect(),
+        }
+    }
+
     /// Add a new [`Link`] to the established chain.
     #[inline]
     pub fn link(mut self, link: Link) -> Self {
@@ -164,8 +524,13 @@ impl Wrappable for Chain {
         let prefix = self.mount_path.clone();
         let guards: Vec<_> = self.guards.drain(0..).collect();
         let next: Vec<_> = self.next.drain(0..).collect();
+        let (buffer_size, file_size) = (self.body_buffer_size, self.body_file_size);
+        let execution = self.execution;
         let link = Link::from(self).wrap_with(middleware);
         let mut chain = Chain::new(&prefix).link(link);
+        chain.body_buffer_size = buffer_size;
+        chain.body_file_size = file_size;
+        chain.execution = execution;
         chain.next = next;
         chain.guards = guards;
         chain
@@ -225,21 +590,56 @@ impl ServiceFactory<ServiceRequest> for Chain {
     type Future = LocalBoxFuture<'static, Result<Self::Service, Self::InitError>>;
 
     fn new_service(&self, _: ()) -> Self::Future {
-        if self.links.is_empty() {
+        if self.links.is_empty() && self.handle.is_none() {
             panic!("Chain contains no links!")
         }
         let this = self.clone();
         Box::pin(async move {
             let mut links = vec![];
-            for link in this.links {
-                match link.inner().await {
+            for (index, link) in this.links.into_iter().enumerate() {
+                match link.inner(index).await {
                     Ok(link) => links.push(link),
                     Err(_) => return Err(()),
                 }
             }
+            let dynamic = this.handle.map(|(position, handle)| DynamicLinks {
+                handle,
+                position,
+                statics: std::mem::take(&mut links),
+                generation: Cell::new(0),
+            });
+            if let Some(dynamic) = dynamic.as_ref() {
+                links = dynamic.build().await?;
+            }
+            let default = match this.default {
+                Some(default) => Some(Rc::new(default.new_service(()).await?)),
+                None => None,
+            };
+            let data = match this.data.is_empty() {
+                true => None,
+                false => {
+                    let mut extensions = Extensions::new();
+                    this.data.iter().for_each(|insert| insert(&mut extensions));
+                    Some(Rc::new(extensions))
+                }
+            };
+            let mut mirrors = vec![];
+            for mirror in this.mirrors {
+                mirrors.push(Rc::new(mirror.inner().await?));
+            }
             Ok(ChainService(Rc::new(ChainInner {
-                links,
+                links: RefCell::new(Rc::new(links)),
+                dynamic,
                 body_buffer_size: this.body_buffer_size,
+                body_file_size: this.body_file_size,
+                execution: this.execution,
+                selection: this.selection,
+                selector: Selector::default(),
+                mirrors,
+                default,
+                exhausted: this.exhausted,
+                debug_header: this.debug_header,
+                data,
             })))
         })
     }
@@ -0,0 +1,289 @@
+//! Runtime reconfiguration for [`Chain::handle`](crate::Chain::handle)
+
+use std::{
+    cell::Cell,
+    rc::Rc,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::link::{Link, LinkInner};
+
+type LinkBuilder = Arc<dyn Fn() -> Link + Send + Sync>;
+
+struct Entry {
+    id: String,
+    enabled: bool,
+    build: LinkBuilder,
+}
+
+/// Ordered set of dynamic links managed by a [`ChainHandle`].
+///
+/// See [`ChainHandle::update`] for more details.
+#[derive(Default)]
+pub struct LinkSet {
+    entries: Vec<Entry>,
+}
+
+impl LinkSet {
+    #[inline]
+    fn position(&self, id: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.id == id)
+    }
+
+    /// Append a new link, replacing any existing link with the same id.
+    ///
+    /// The builder is called on every worker to construct the link.
+    pub fn push<F>(&mut self, id: impl Into<String>, build: F) -> &mut Self
+    where
+        F: Fn() -> Link + Send + Sync + 'static,
+    {
+        let index = self.entries.len();
+        self.insert(index, id, build)
+    }
+
+    /// Insert a new link at the given index, replacing any existing
+    /// link with the same id.
+    ///
+    /// The index is clamped to the number of links.
+    pub fn insert<F>(&mut self, index: usize, id: impl Into<String>, build: F) -> &mut Self
+    where
+        F: Fn() -> Link + Send + Sync + 'static,
+    {
+        let id = id.into();
+        self.remove(&id);
+        let index = index.min(self.entries.len());
+        self.entries.insert(
+            index,
+            Entry {
+                id,
+                enabled: true,
+                build: Arc::new(build),
+            },
+        );
+        self
+    }
+
+    /// Remove the link with the given id.
+    ///
+    /// Returns true if the link existed.
+    pub fn remove(&mut self, id: &str) -> bool {
+        match self.position(id) {
+            Some(pos) => {
+                self.entries.remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move the link with the given id to a new index.
+    ///
+    /// Returns true if the link existed.
+    pub fn move_to(&mut self, id: &str, index: usize) -> bool {
+        let Some(pos) = self.position(id) else {
+            return false;
+        };
+        let entry = self.entries.remove(pos);
+        let index = index.min(self.entries.len());
+        self.entries.insert(index, entry);
+        true
+    }
+
+    /// Enable or disable the link with the given id.
+    ///
+    /// Disabled links are kept in place but skipped by the chain.
+    /// Returns true if the link existed.
+    pub fn set_enabled(&mut self, id: &str, enabled: bool) -> bool {
+        match self.position(id) {
+            Some(pos) => {
+                self.entries[pos].enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// List the link ids in order along with whether they are enabled.
+    pub fn ids(&self) -> Vec<(String, bool)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.id.clone(), entry.enabled))
+            .collect()
+    }
+}
+
+struct HandleState {
+    generation: AtomicU64,
+    links: Mutex<LinkSet>,
+}
+
+/// Handle used to reconfigure the links of a running [`Chain`](crate::Chain).
+///
+/// The handle is cheap to clone and can be shared between threads.
+/// Create it outside the `HttpServer` factory and attach it to the chain
+/// of every worker with [`Chain::handle`](crate::Chain::handle).
+///
+/// Every update is applied atomically. Workers rebuild their links on the
+/// next request after an update, while in-flight requests keep using the
+/// links they started with.
+///
+/// # Examples
+/// ```
+/// use actix_web::{App, web};
+/// use actix_chain::{Chain, ChainHandle, Link};
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// let handle = ChainHandle::default();
+/// handle.push("index", || Link::new(web::get().to(index)));
+///
+/// // inside the `HttpServer::new` factory
+/// let app = App::new().service(Chain::default().handle(handle.clone()));
+///
+/// // later, while the server is running
+/// handle.update(|links| {
+///     links.set_enabled("index", false);
+///     links.push("backup", || Link::new(web::get().to(index)));
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ChainHandle(Arc<HandleState>);
+
+impl Default for ChainHandle {
+    fn default() -> Self {
+        Self(Arc::new(HandleState {
+            generation: AtomicU64::new(0),
+            links: Mutex::new(LinkSet::default()),
+        }))
+    }
+}
+
+impl ChainHandle {
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, LinkSet> {
+        self.0.links.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Apply one or more changes to the link set as a single update.
+    pub fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut LinkSet) -> R,
+    {
+        let mut links = self.lock();
+        let result = f(&mut links);
+        self.0.generation.fetch_add(1, Ordering::AcqRel);
+        result
+    }
+
+    /// Append a new link. See [`LinkSet::push`].
+    pub fn push<F>(&self, id: impl Into<String>, build: F)
+    where
+        F: Fn() -> Link + Send + Sync + 'static,
+    {
+        self.update(|links| {
+            links.push(id, build);
+        })
+    }
+
+    /// Insert a new link at the given index. See [`LinkSet::insert`].
+    pub fn insert<F>(&self, index: usize, id: impl Into<String>, build: F)
+    where
+        F: Fn() -> Link + Send + Sync + 'static,
+    {
+        self.update(|links| {
+            links.insert(index, id, build);
+        })
+    }
+
+    /// Remove the link with the given id. See [`LinkSet::remove`].
+    pub fn remove(&self, id: &str) -> bool {
+        self.update(|links| links.remove(id))
+    }
+
+    /// Move the link with the given id. See [`LinkSet::move_to`].
+    pub fn move_to(&self, id: &str, index: usize) -> bool {
+        self.update(|links| links.move_to(id, index))
+    }
+
+    /// Enable or disable the link with the given id. See [`LinkSet::set_enabled`].
+    pub fn set_enabled(&self, id: &str, enabled: bool) -> bool {
+        self.update(|links| links.set_enabled(id, enabled))
+    }
+
+    /// List the link ids in order along with whether they are enabled.
+    pub fn ids(&self) -> Vec<(String, bool)> {
+        self.lock().ids()
+    }
+
+    /// Current generation of the link set
+    #[inline]
+    pub(crate) fn generation(&self) -> u64 {
+        self.0.generation.load(Ordering::Acquire)
+    }
+
+    /// Retrieve the generation and builders of all enabled links
+    pub(crate) fn snapshot(&self) -> (u64, Vec<(String, LinkBuilder)>) {
+        let links = self.lock();
+        let builders = links
+            .entries
+            .iter()
+            .filter(|entry| entry.enabled)
+            .map(|entry| (entry.id.clone(), Arc::clone(&entry.build)))
+            .collect();
+        (self.generation(), builders)
+    }
+}
+
+/// Per-worker state of links managed by a [`ChainHandle`].
+pub(crate) struct DynamicLinks {
+    pub(crate) handle: ChainHandle,
+    /// Position of the dynamic links among the static links
+    pub(crate) position: usize,
+    pub(crate) statics: Vec<LinkInner>,
+    pub(crate) generation: Cell<u64>,
+}
+
+impl DynamicLinks {
+    /// Check if the handle has been updated since the last build
+    #[inline]
+    pub(crate) fn is_stale(&self) -> bool {
+        self.handle.generation() != self.generation.get()
+    }
+
+    /// Build the chain links from the latest generation of the handle
+    pub(crate) async fn build(&self) -> Result<Vec<LinkInner>, ()> {
+        let (generation, builders) = self.handle.snapshot();
+        let mut links = self.statics[..self.position].to_vec();
+        for (id, build) in builders {
+            let mut link = build();
+            if link.name.is_none() {
+                link.name = Some(Rc::from(id.as_str()));
+            }
+            match link.inner(links.len()).await {
+                Ok(link) => links.push(link),
+                Err(_) => {
+                    tracing::error!("failed to build chain link {id:?}");
+                    return Err(());
+                }
+            }
+        }
+        links.extend_from_slice(&self.statics[self.position..]);
+        links
+            .iter_mut()
+            .enumerate()
+            .for_each(|(index, link)| link.index = index);
+
+        // failed builds keep the handle stale so they are retried
+        self.generation.set(generation);
+        tracing::info!(
+            "chain links updated generation={generation} links={}",
+            links.len()
+        );
+        Ok(links)
+    }
+}
@@ -26,14 +26,34 @@
 //! );
 //! ```
 
+mod body;
+mod breaker;
+mod concurrent;
+mod copy;
+mod explain;
 mod factory;
+mod handle;
 mod link;
+mod mirror;
 pub mod next;
 mod payload;
+mod previous;
+mod retry;
+mod select;
 mod service;
+pub mod test;
+mod trace;
 mod wrap;
 
-pub use factory::Chain;
-pub use link::Link;
+pub use breaker::{CircuitBreaker, CircuitState};
+pub use explain::{ChainExplain, LinkExplain};
+pub use factory::{Chain, Execution, Exhausted};
+pub use handle::{ChainHandle, LinkSet};
+pub use link::{Link, OnError};
+pub use mirror::{Mirror, MirrorStats};
+pub use previous::PreviousResponse;
+pub use retry::Retry;
+pub use select::Selection;
 pub use service::ChainService;
+pub use trace::{ChainTrace, LinkAttempt};
 pub use wrap::Wrappable;
@@ -1,20 +1,28 @@
-use std::{rc::Rc, str::FromStr};
+use std::{fmt, rc::Rc, str::FromStr, time::Duration};
 
 use actix_service::{IntoServiceFactory, ServiceFactory, ServiceFactoryExt, Transform, boxed};
 use actix_web::{
-    Error, HttpResponse,
+    Error, HttpRequest, HttpResponse,
     body::MessageBody,
-    dev::{ServiceRequest, ServiceResponse},
+    dev::{Path, ResourceDef, ServiceRequest, ServiceResponse, Url},
     guard::{Guard, GuardContext},
     http::{StatusCode, Uri, header, uri::PathAndQuery},
     middleware::Compat,
     mime,
+    rt::time::sleep,
 };
 
 use crate::{
     Chain,
-    next::{IsStatus, Next},
+    body::buffer_body,
+    breaker::CircuitBreaker,
+    explain::LinkExplain,
+    next::{IsStatus, Next, NextBody},
+    payload::PayloadRef,
+    previous::PreviousResponse,
+    retry::{Retry, RetryInner},
     service::{HttpNewService, HttpService},
+    trace::ChainTrace,
     wrap::Wrappable,
 };
 
@@ -40,14 +48,36 @@ use crate::{
 /// ```
 #[derive(Clone)]
 pub struct Link {
+    pub(crate) name: Option<Rc<str>>,
     pub(crate) prefix: String,
     pub(crate) guards: Vec<Rc<dyn Guard>>,
     pub(crate) next: Vec<Rc<dyn Next>>,
+    pub(crate) next_body: Vec<Rc<dyn NextBody>>,
+    pub(crate) body_limit: usize,
+    pub(crate) on_error: OnError,
+    pub(crate) weight: u32,
+    pub(crate) breaker: Option<CircuitBreaker>,
+    pub(crate) retry: Option<Retry>,
     pub(crate) service: Rc<HttpNewService>,
+    pub(crate) chain: Option<Rc<Chain>>, // For Chain::explain only
+}
+
+/// [`Link`] behavior when its service responds with an error.
+///
+/// See [`Link::on_error`] for more details.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Evaluate error responses by the link's [`Next`] criteria
+    /// like any other response.
+    #[default]
+    Propagate,
+    /// Forward the request to the next link whenever the service
+    /// responds with an error.
+    Next,
 }
 
 #[inline]
-fn box_factory<F, U>(service: F) -> Rc<HttpNewService>
+pub(crate) fn box_factory<F, U>(service: F) -> Rc<HttpNewService>
 where
     F: IntoServiceFactory<U, ServiceRequest>,
     U: ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse, Error = Error>
@@ -67,17 +97,51 @@ impl Link {
             + 'static,
     {
         Self {
+            name: None,
             prefix: String::new(),
             guards: Vec::new(),
             next: Vec::new(),
+            next_body: Vec::new(),
+            body_limit: 64 * 1024, // 64 kb default
+            on_error: OnError::default(),
+            weight: 1,
+            breaker: None,
+            retry: None,
             service: box_factory(service),
+            chain: None,
         }
     }
 
+    /// Assign a name to the link.
+    ///
+    /// The name is used in logs and the [`ChainTrace`](crate::ChainTrace)
+    /// in place of the link's position within the chain.
+    pub fn name<S: AsRef<str>>(mut self, name: S) -> Self {
+        self.name = Some(Rc::from(name.as_ref()));
+        self
+    }
+
     /// Assign a `match-prefix` / `mount_path` to the link.
     ///
     /// The prefix is the root URL at which the service is used.
     /// For example, /assets will serve files at example.com/assets/....
+    ///
+    /// The prefix may be any [`ResourceDef`] pattern such as `/users/{id}`,
+    /// which is matched against whole path segments. The matched portion of
+    /// the path is stripped before calling the service, and any captured
+    /// parameters are made available through the request `match_info`.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::{HttpRequest, web};
+    /// use actix_chain::Link;
+    ///
+    /// async fn user(req: HttpRequest) -> String {
+    ///     format!("user {}", req.match_info().get("id").unwrap_or_default())
+    /// }
+    ///
+    /// Link::new(web::get().to(user)).prefix("/users/{id}");
+    /// ```
     pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
         self.prefix = prefix.into();
         self
@@ -120,6 +184,10 @@ impl Link {
     /// The default [`Link`] behavior is to continue down the chain
     /// on "404 Not Found" and "405 Method Not Allowed" responses only.
     ///
+    /// Calling this method multiple times forwards when any of the supplied
+    /// criteria match. See [`NextExt`](crate::next::NextExt) for more
+    /// complex combinations.
+    ///
     /// # Examples
     /// ```
     /// use actix_web::{http::StatusCode, web};
@@ -140,6 +208,149 @@ impl Link {
         self
     }
 
+    /// Configure when a [`Link`] should forward to the next chain based on
+    /// the content of the response body.
+    ///
+    /// The response body is buffered up to the [`Link::next_body_limit`]
+    /// and passed to the supplied [`NextBody`] criteria, which are only
+    /// evaluated when none of the [`Link::next`] criteria match. Bodies
+    /// exceeding the limit never match. The body is reattached to the
+    /// response when it is returned.
+    ///
+    /// The default "404 Not Found" and "405 Method Not Allowed" criteria
+    /// are replaced when either this method or [`Link::next`] is called.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::{http::StatusCode, web};
+    /// use actix_chain::{Link, next::{IsStatus, JsonPointer}};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// Link::new(web::get().to(index))
+    ///     .next(IsStatus(StatusCode::NOT_FOUND))
+    ///     .next_body(JsonPointer::new("/error", "not_found"));
+    /// ```
+    pub fn next_body<N>(mut self, next: N) -> Self
+    where
+        N: NextBody + 'static,
+    {
+        self.next_body.push(Rc::new(next));
+        self
+    }
+
+    /// Set the maximum response body size buffered for [`Link::next_body`].
+    ///
+    /// Default is 64KiB.
+    pub fn next_body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    /// Configure how error responses of the link's service are handled.
+    ///
+    /// Services report failures either as an error response, built with
+    /// [`ServiceResponse::from_err`] as handlers and `RevProxy` do, or by
+    /// returning the error itself. The default [`OnError::Propagate`]
+    /// behavior evaluates error responses by the link's [`Next`] criteria,
+    /// while [`OnError::Next`] always forwards them to the next link.
+    ///
+    /// A returned error consumes the request, so it is always returned
+    /// immediately, skipping any remaining links in the chain.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::{http::StatusCode, web};
+    /// use actix_chain::{Chain, Link, OnError};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// Chain::default()
+    ///     .link(Link::new(web::get().to(index))
+    ///         .on_error(OnError::Next))
+    ///     .link(Link::new(web::get().to(index)));
+    /// ```
+    pub fn on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Assign the link's weight used by the chain's [`Selection`](crate::Selection)
+    /// strategy.
+    ///
+    /// Links with a higher weight are chosen proportionally more often, and
+    /// links with a weight of zero are only ever used as fallbacks.
+    ///
+    /// Default is 1.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::web;
+    /// use actix_chain::{Chain, Link, Selection};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// Chain::default()
+    ///     .selection(Selection::WeightedRandom)
+    ///     .link(Link::new(web::get().to(index)).weight(3))
+    ///     .link(Link::new(web::get().to(index)));
+    /// ```
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Assign a [`CircuitBreaker`] to the link.
+    ///
+    /// Links with an open circuit are skipped entirely, and the request
+    /// is forwarded to the next link in the chain instead.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use actix_web::web;
+    /// use actix_chain::{CircuitBreaker, Link};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// Link::new(web::get().to(index))
+    ///     .circuit_breaker(CircuitBreaker::new(5, Duration::from_secs(10)));
+    /// ```
+    pub fn circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Assign a [`Retry`] policy to the link.
+    ///
+    /// Retried requests replay the request body buffered by the chain,
+    /// so bodies larger than the chain's buffer limits are not retried.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::web;
+    /// use actix_chain::{Link, Retry};
+    ///
+    /// async fn index() -> &'static str {
+    ///     "Hello world!"
+    /// }
+    ///
+    /// Link::new(web::get().to(index)).retry(Retry::new(3));
+    /// ```
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
     /// Registers a link specific middleware.
     ///
     /// Wrapping a link advantagously does not construct
@@ -177,13 +388,56 @@ impl Link {
         self.wrap_with(middleware)
     }
 
+    /// Build the [`ResourceDef`] used to match the link prefix
+    fn resource_def(&self) -> Option<ResourceDef> {
+        let prefix = self.prefix.trim_end_matches('/');
+        match prefix.is_empty() {
+            true => None,
+            false => Some(ResourceDef::root_prefix(prefix)),
+        }
+    }
+
+    /// Evaluate the link against the request, as if it were sent to `uri`,
+    /// without calling its service.
+    pub(crate) fn explain(&self, index: usize, req: &ServiceRequest, uri: &Uri) -> LinkExplain {
+        let stripped = match self.resource_def() {
+            Some(rdef) => strip_prefix(&rdef, uri),
+            None => Some((uri.clone(), Path::new(Url::new(uri.clone())))),
+        };
+        let ctx = req.guard_ctx();
+        let mut explain = LinkExplain {
+            index,
+            name: self.name.clone(),
+            prefix: self.prefix.clone(),
+            prefix_matched: stripped.is_some(),
+            guards_passed: self.guards.iter().all(|g| g.check(&ctx)),
+            uri: None,
+            params: Vec::new(),
+            nested: None,
+        };
+        let Some((uri, path)) = stripped else {
+            return explain;
+        };
+        explain.params = path
+            .iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        if explain.matched()
+            && let Some(chain) = self.chain.as_ref()
+        {
+            explain.nested = Some(chain.explain_uri(req, &uri));
+        }
+        explain.uri = Some(uri);
+        explain
+    }
+
     /// Convert public [`Link`] builder into [`LinkInner`]
-    pub(crate) async fn inner(&self) -> Result<LinkInner, ()> {
+    pub(crate) async fn inner(&self, index: usize) -> Result<LinkInner, ()> {
         let guard = match self.guards.is_empty() {
             true => None,
             false => Some(AllGuard(self.guards.clone())),
         };
-        let next: Vec<Rc<dyn Next>> = match self.next.is_empty() {
+        let next: Vec<Rc<dyn Next>> = match self.next.is_empty() && self.next_body.is_empty() {
             true => vec![
                 IsStatus::rc(StatusCode::NOT_FOUND),
                 IsStatus::rc(StatusCode::METHOD_NOT_ALLOWED),
@@ -191,9 +445,17 @@ impl Link {
             false => self.next.clone(),
         };
         Ok(LinkInner {
+            index,
+            name: self.name.clone(),
+            rdef: self.resource_def(),
             guard,
             next,
-            prefix: self.prefix.clone(),
+            on_error: self.on_error,
+            weight: self.weight,
+            next_body: self.next_body.clone(),
+            body_limit: self.body_limit,
+            breaker: self.breaker.clone(),
+            retry: self.retry.as_ref().map(|retry| retry.inner()),
             service: Rc::new(self.service.new_service(()).await?),
         })
     }
@@ -223,14 +485,27 @@ impl From<Chain> for Link {
         let prefix = value.mount_path.clone();
         let guards: Vec<_> = value.guards.drain(0..).collect();
         let next: Vec<_> = value.next.drain(0..).collect();
+        let chain = Rc::new(value.clone());
         let mut link = Self::new(value).prefix(&prefix);
+        link.chain = Some(chain);
         link.guards = guards;
         link.next = next;
         link
     }
 }
 
-struct AllGuard(Vec<Rc<dyn Guard>>);
+/// Outcome of calling a [`LinkInner`] service.
+pub(crate) enum Outcome {
+    /// Service produced a response.
+    Response(ServiceResponse),
+    /// Service produced a response matching the link's [`Next`] criteria.
+    Rejected(ServiceResponse),
+    /// Service failed and the request should continue to the next link.
+    Next(HttpRequest),
+}
+
+#[derive(Clone)]
+pub(crate) struct AllGuard(pub(crate) Vec<Rc<dyn Guard>>);
 
 impl Guard for AllGuard {
     #[inline]
@@ -249,51 +524,206 @@ pub(crate) fn default_response(req: ServiceRequest) -> ServiceResponse {
     )
 }
 
+/// Match the prefix against the URI, returning the URI with the matched
+/// prefix removed and any captured parameters
+fn strip_prefix(rdef: &ResourceDef, uri: &Uri) -> Option<(Uri, Path<Url>)> {
+    let mut path = Path::new(Url::new(uri.clone()));
+    if !rdef.capture_match_info(&mut path) {
+        return None;
+    }
+    // decoded path retains all path delimiters so strip by segment
+    // count to preserve the original encoding of the remaining path
+    let matched = path.as_str().len() - path.unprocessed().len();
+    let segments = path.as_str()[..matched].matches('/').count();
+    Some((strip_segments(uri, segments), path))
+}
+
+/// Remove the first `segments` path segments from the URI
+fn strip_segments(uri: &Uri, segments: usize) -> Uri {
+    let path = uri.path();
+    let rest = match path.match_indices('/').nth(segments) {
+        Some((idx, _)) => &path[idx..],
+        None => "/",
+    };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{rest}?{query}"),
+        None => rest.to_owned(),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = PathAndQuery::from_str(&path_and_query).ok();
+    Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+}
+
+#[derive(Clone)]
 pub(crate) struct LinkInner {
-    prefix: String,
+    pub(crate) index: usize,
+    name: Option<Rc<str>>,
+    rdef: Option<ResourceDef>,
     guard: Option<AllGuard>,
     pub(crate) service: Rc<HttpService>,
     pub(crate) next: Vec<Rc<dyn Next>>,
+    pub(crate) next_body: Vec<Rc<dyn NextBody>>,
+    pub(crate) body_limit: usize,
+    pub(crate) on_error: OnError,
+    pub(crate) weight: u32,
+    pub(crate) breaker: Option<CircuitBreaker>,
+    pub(crate) retry: Option<RetryInner>,
 }
 
 impl LinkInner {
-    /// Generate new URI with prefix stripped if prefix is not empty
-    pub(crate) fn new_uri(&self, uri: &Uri) -> Option<Uri> {
-        if self.prefix.is_empty() {
-            return None;
+    /// Strip the matched prefix from the request URI and add any captured
+    /// parameters to the request `match_info`.
+    ///
+    /// Returns the original URI and match info so they can be restored.
+    pub(crate) fn apply(&self, req: &mut ServiceRequest) -> Option<(Uri, Path<Url>)> {
+        let (uri, path) = strip_prefix(self.rdef.as_ref()?, req.uri())?;
+        let original = (req.uri().clone(), req.match_info().clone());
+        let info = req.match_info_mut();
+        for (name, value) in path.iter() {
+            info.add_static(name.to_owned(), value.to_owned());
         }
-        let mut parts = uri.clone().into_parts();
-        parts.path_and_query = parts
-            .path_and_query
-            .and_then(|pq| PathAndQuery::from_str(pq.as_str().strip_prefix(&self.prefix)?).ok());
-        Uri::from_parts(parts).ok()
+        req.head_mut().uri = uri;
+        Some(original)
     }
 
     /// Check if request path matches prefix and any guards are met
     #[inline]
     pub(crate) fn matches(&self, path: &str, ctx: &GuardContext) -> bool {
-        path.starts_with(&self.prefix) && self.guard.as_ref().map(|g| !g.check(ctx)).unwrap_or(true)
+        self.rdef.as_ref().is_none_or(|rdef| rdef.is_match(path))
+            && self.guard.as_ref().is_none_or(|g| g.check(ctx))
     }
 
-    /// Check if response is invalid, and next link should execute
+    /// Record the result of calling the link in the chain trace, along
+    /// with the trace of any nested chain that produced the response
+    pub(crate) fn trace(
+        &self,
+        trace: &mut ChainTrace,
+        result: &Result<Outcome, Error>,
+        elapsed: Duration,
+    ) {
+        let (status, nested) = match result {
+            Ok(Outcome::Response(res) | Outcome::Rejected(res)) => (
+                Some(res.status()),
+                res.response().extensions().get::<ChainTrace>().cloned(),
+            ),
+            _ => (None, None),
+        };
+        trace.record(self.index, self.name.clone(), status, elapsed, nested);
+    }
+
+    /// Collect the details of a response discarded by the link
     #[inline]
-    pub(crate) fn go_next(&self, res: &HttpResponse) -> bool {
-        self.next.iter().any(|next| next.next(res))
+    pub(crate) fn previous(&self, res: HttpResponse) -> PreviousResponse {
+        PreviousResponse::new(self.index, self.name.clone(), res)
     }
 
-    /// Call inner service once and return [`actix_web::dev::ServiceResponse`]
-    /// no matter what.
+    /// Check if the link's circuit breaker is not open
     #[inline]
-    pub(crate) async fn call_once(
+    pub(crate) fn is_available(&self) -> bool {
+        self.breaker.as_ref().is_none_or(|b| !b.is_open())
+    }
+
+    /// Check if response is invalid, and next link should execute
+    async fn go_next(&self, res: ServiceResponse) -> (ServiceResponse, bool) {
+        if self.next.iter().any(|next| next.next(res.response())) {
+            return (res, true);
+        }
+        if self.next_body.is_empty() {
+            return (res, false);
+        }
+        let (res, body) = buffer_body(res, self.body_limit).await;
+        let Some(body) = body else {
+            return (res, false);
+        };
+        for next in self.next_body.iter() {
+            if next.next(res.response(), &body).await {
+                return (res, true);
+            }
+        }
+        (res, false)
+    }
+
+    /// Call inner service and evaluate its response against the link's
+    /// [`Next`] criteria.
+    pub(crate) async fn call_next(
+        &self,
+        req: ServiceRequest,
+        buf: &PayloadRef,
+    ) -> Result<Outcome, Error> {
+        match self.call(req, Some(buf)).await? {
+            Outcome::Response(res) => match self.go_next(res).await {
+                (res, true) => Ok(Outcome::Rejected(res)),
+                (res, false) => Ok(Outcome::Response(res)),
+            },
+            outcome => Ok(outcome),
+        }
+    }
+
+    /// Call inner service, retrying according to the configured [`Retry`]
+    /// policy when the buffered request payload can be replayed.
+    pub(crate) async fn call(
         &self,
         mut req: ServiceRequest,
-    ) -> Result<ServiceResponse, Error> {
-        if !self.matches(req.uri().path(), &req.guard_ctx()) {
-            return Ok(default_response(req));
+        buf: Option<&PayloadRef>,
+    ) -> Result<Outcome, Error> {
+        let (retry, buf) = match (self.retry.as_ref(), buf) {
+            (Some(retry), Some(buf)) if retry.allows(req.method()) => (retry, buf),
+            _ => return self.attempt(req).await,
+        };
+        let mut attempt = 1;
+        loop {
+            let res = match self.attempt(req).await? {
+                Outcome::Response(res) => res,
+                outcome => return Ok(outcome),
+            };
+            if attempt >= retry.attempts || !retry.retryable(res.response()) || !buf.replayable() {
+                return Ok(Outcome::Response(res));
+            }
+            let delay = retry.delay(attempt);
+            tracing::debug!(
+                "link {self} attempt {attempt} response={:?}, retrying in {delay:?}",
+                res.status()
+            );
+            let (http_req, _) = res.into_parts();
+            sleep(delay).await;
+            req = ServiceRequest::from_parts(http_req, buf.payload());
+            attempt += 1;
+        }
+    }
+
+    /// Call inner service once and apply the configured [`OnError`] policy.
+    async fn attempt(&self, req: ServiceRequest) -> Result<Outcome, Error> {
+        let permit = match self.breaker.as_ref().map(|b| b.acquire()) {
+            Some(None) => {
+                tracing::debug!("link circuit breaker open, skipping");
+                return Ok(Outcome::Next(req.into_parts().0));
+            }
+            permit => permit.flatten(),
+        };
+        let result = self.service.call(req).await;
+        if let Some(permit) = permit {
+            permit.record(
+                result
+                    .as_ref()
+                    .is_ok_and(|res| !res.status().is_server_error()),
+            );
         }
-        if let Some(uri) = self.new_uri(req.uri()) {
-            req.head_mut().uri = uri;
+        let res = result?;
+        if self.on_error == OnError::Next
+            && let Some(err) = res.response().error()
+        {
+            tracing::debug!("link service error={err:?}, continuing");
+            return Ok(Outcome::Next(res.into_parts().0));
+        }
+        Ok(Outcome::Response(res))
+    }
+}
+
+impl fmt::Display for LinkInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name.as_ref() {
+            Some(name) => write!(f, "{} ({name})", self.index),
+            None => write!(f, "{}", self.index),
         }
-        self.service.call(req).await
     }
 }
@@ -0,0 +1,300 @@
+//! Traffic mirroring for [`Chain::mirror`](crate::Chain::mirror)
+
+use std::{
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use actix_service::{IntoServiceFactory, ServiceFactory};
+use actix_web::{
+    Error,
+    dev::{Extensions, Payload, Service, ServiceRequest, ServiceResponse},
+    guard::Guard,
+    http::StatusCode,
+};
+
+use crate::{
+    copy::RequestCopy,
+    link::{AllGuard, box_factory},
+    payload::PayloadRef,
+    service::{HttpNewService, HttpService},
+};
+
+/// Shadow service which receives a copy of requests sent to a
+/// [`Chain`](crate::Chain).
+///
+/// Mirrored requests are sent in the background once the chain has
+/// produced its response, and the mirror's response is discarded.
+/// Each mirrored request receives its own copy of the request head
+/// and the buffered request body. The copy keeps the app config and
+/// match info, but only carries the app data registered with
+/// [`Chain::app_data`](crate::Chain::app_data).
+///
+/// # Examples
+/// ```
+/// use actix_web::web;
+/// use actix_chain::{Chain, Link, Mirror};
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// Chain::default()
+///     .link(Link::new(web::get().to(index)))
+///     .mirror(Mirror::new(web::get().to(index))
+///         .sample(0.1)
+///         .max_concurrent(16)
+///         .compare(true));
+/// ```
+#[derive(Clone)]
+pub struct Mirror {
+    guards: Vec<Rc<dyn Guard>>,
+    sample: f64,
+    max_concurrent: usize,
+    compare: bool,
+    stats: Arc<MirrorStats>,
+    service: Rc<HttpNewService>,
+}
+
+/// Counters collected by a [`Mirror`].
+///
+/// See [`Mirror::stats`] to share the counters between workers.
+#[derive(Debug, Default)]
+pub struct MirrorStats {
+    in_flight: AtomicUsize,
+    sent: AtomicU64,
+    skipped: AtomicU64,
+    errors: AtomicU64,
+    mismatches: AtomicU64,
+}
+
+impl MirrorStats {
+    /// Number of mirrored requests currently running.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Number of mirrored requests that completed.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Number of sampled requests skipped due to the concurrency limit.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Number of mirrored requests that returned a service error.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Number of mirrored responses with a status different
+    /// from the primary response.
+    ///
+    /// Only recorded when [`Mirror::compare`] is enabled.
+    pub fn mismatches(&self) -> u64 {
+        self.mismatches.load(Ordering::Relaxed)
+    }
+}
+
+impl Mirror {
+    /// Create a new [`Mirror`] for your [`Chain`](crate::Chain).
+    ///
+    /// Any Actix-Web service can be passed such as [`actix_web::Route`].
+    pub fn new<F, U>(service: F) -> Self
+    where
+        F: IntoServiceFactory<U, ServiceRequest>,
+        U: ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse, Error = Error>
+            + 'static,
+    {
+        Self {
+            guards: Vec::new(),
+            sample: 1.0,
+            max_concurrent: 64,
+            compare: false,
+            stats: Arc::default(),
+            service: box_factory(service),
+        }
+    }
+
+    /// Adds a guard which requests must match to be mirrored.
+    pub fn guard<G: Guard + 'static>(mut self, guards: G) -> Self {
+        self.guards.push(Rc::new(guards));
+        self
+    }
+
+    /// Set the fraction of matching requests that are mirrored,
+    /// between `0.0` and `1.0`.
+    ///
+    /// Default is 1.0 (every request).
+    pub fn sample(mut self, rate: f64) -> Self {
+        self.sample = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set the maximum number of mirrored requests running at once.
+    ///
+    /// Sampled requests beyond the limit are skipped.
+    ///
+    /// Default is 64.
+    pub fn max_concurrent(mut self, max: usize) -> Self {
+        self.max_concurrent = max;
+        self
+    }
+
+    /// Compare the mirrored response status against the primary
+    /// response and record any mismatch.
+    ///
+    /// Default is false.
+    pub fn compare(mut self, compare: bool) -> Self {
+        self.compare = compare;
+        self
+    }
+
+    /// Assign the [`MirrorStats`] counters used by the mirror.
+    ///
+    /// The concurrency limit applies to the in-flight requests of the
+    /// assigned counters, so sharing them between workers also shares
+    /// the limit.
+    pub fn stats(mut self, stats: Arc<MirrorStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Convert public [`Mirror`] builder into [`MirrorInner`]
+    pub(crate) async fn inner(&self) -> Result<MirrorInner, ()> {
+        let guard = match self.guards.is_empty() {
+            true => None,
+            false => Some(AllGuard(self.guards.clone())),
+        };
+        Ok(MirrorInner {
+            guard,
+            sample: self.sample,
+            max_concurrent: self.max_concurrent,
+            compare: self.compare,
+            stats: self.stats.clone(),
+            service: Rc::new(self.service.new_service(()).await?),
+        })
+    }
+}
+
+pub(crate) struct MirrorInner {
+    guard: Option<AllGuard>,
+    sample: f64,
+    max_concurrent: usize,
+    compare: bool,
+    stats: Arc<MirrorStats>,
+    service: Rc<HttpService>,
+}
+
+/// Reserved slot within the mirror concurrency limit.
+struct Slot(Arc<MirrorStats>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Mirrored request waiting on the primary response.
+pub(crate) struct Pending {
+    mirror: Rc<MirrorInner>,
+    copy: RequestCopy,
+    payload: Payload,
+    slot: Slot,
+}
+
+impl MirrorInner {
+    #[inline]
+    fn sampled(&self) -> bool {
+        if self.sample >= 1.0 {
+            return true;
+        }
+        rand::random::<f64>() < self.sample
+    }
+
+    fn reserve(&self) -> Option<Slot> {
+        let in_flight = self.stats.in_flight.fetch_add(1, Ordering::Relaxed);
+        let slot = Slot(self.stats.clone());
+        if in_flight >= self.max_concurrent {
+            self.stats.skipped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(slot)
+    }
+
+    /// Snapshot the request for the mirror if it matches and is sampled.
+    ///
+    /// The mirror receives a copy of the request built once it is sent,
+    /// so it does not share the request with the chain's links.
+    pub(crate) fn prepare(
+        self: &Rc<Self>,
+        req: &ServiceRequest,
+        buf: &PayloadRef,
+        data: Option<&Rc<Extensions>>,
+    ) -> Option<Pending> {
+        if !self
+            .guard
+            .as_ref()
+            .is_none_or(|g| g.check(&req.guard_ctx()))
+            || !self.sampled()
+        {
+            return None;
+        }
+        let slot = self.reserve()?;
+        Some(Pending {
+            mirror: Rc::clone(self),
+            copy: RequestCopy::new(req.request(), data),
+            payload: buf.payload(),
+            slot,
+        })
+    }
+}
+
+impl Pending {
+    /// Send the mirrored request in the background
+    pub(crate) fn spawn(self, primary: Option<StatusCode>) {
+        actix_web::rt::spawn(async move {
+            let Pending {
+                mirror,
+                copy,
+                payload,
+                slot,
+            } = self;
+            let stats = &mirror.stats;
+            let path = copy.path();
+            let result = match copy.build().await {
+                Ok(mut req) => {
+                    req.set_payload(payload);
+                    mirror.service.call(req).await
+                }
+                Err(err) => Err(err),
+            };
+            stats.sent.fetch_add(1, Ordering::Relaxed);
+            match result {
+                Ok(res) => {
+                    tracing::debug!("mirror {path:?} response={:?}", res.status());
+                    if mirror.compare
+                        && let Some(status) = primary
+                        && status != res.status()
+                    {
+                        stats.mismatches.fetch_add(1, Ordering::Relaxed);
+                        tracing::warn!(
+                            "mirror {path:?} status mismatch primary={status:?} mirror={:?}",
+                            res.status()
+                        );
+                    }
+                }
+                Err(err) => {
+                    stats.errors.fetch_add(1, Ordering::Relaxed);
+                    tracing::debug!("mirror {path:?} error={err:?}");
+                }
+            }
+            drop(slot);
+        });
+    }
+}
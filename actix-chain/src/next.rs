@@ -1,21 +1,125 @@
 //! All tools and utilities related to [`Link::next`](crate::Link::next)
+//! and [`Link::next_body`](crate::Link::next_body)
 
-use std::rc::Rc;
+use std::{future::ready, ops::RangeInclusive, rc::Rc};
 
 use actix_web::{
     HttpResponse,
-    http::{StatusCode, header::HeaderName},
+    http::{
+        StatusCode,
+        header::{self, HeaderName, HeaderValue},
+    },
+    mime::Mime,
+    web::Bytes,
 };
+use futures_core::future::LocalBoxFuture;
+use regex_lite::Regex;
+use serde_json::Value;
 
 /// Response equivalent of [`actix_web::guard::Guard`].
 ///
 /// Blocks responses that contain matching criteria
 /// and allows the request to be forwarded to the next
 /// [`Link`](crate::Link) in the [`Chain`](crate::Chain).
+///
+/// Implemented for any `Fn(&HttpResponse) -> bool` closure.
+///
+/// # Examples
+/// ```
+/// use actix_web::{HttpResponse, web};
+/// use actix_chain::Link;
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// Link::new(web::get().to(index))
+///     .next(|res: &HttpResponse| res.status().is_server_error());
+/// ```
 pub trait Next {
     fn next(&self, res: &HttpResponse) -> bool;
 }
 
+impl<F> Next for F
+where
+    F: Fn(&HttpResponse) -> bool,
+{
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        (self)(res)
+    }
+}
+
+/// Boolean combinators for [`Next`] implementations.
+///
+/// # Examples
+///
+/// Fall through on "404 Not Found" unless `X-Final` is set.
+///
+/// ```
+/// use actix_web::{http::{StatusCode, header::HeaderName}, web};
+/// use actix_chain::{Link, next::{HasHeader, IsStatus, NextExt}};
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// let final_header = HeaderName::from_static("x-final");
+/// Link::new(web::get().to(index))
+///     .next(IsStatus(StatusCode::NOT_FOUND).and(HasHeader(final_header).not()));
+/// ```
+pub trait NextExt: Next + Sized {
+    /// Forward only if both `self` and `other` match.
+    #[inline]
+    fn and<N: Next>(self, other: N) -> And<Self, N> {
+        And(self, other)
+    }
+
+    /// Forward if either `self` or `other` match.
+    #[inline]
+    fn or<N: Next>(self, other: N) -> Or<Self, N> {
+        Or(self, other)
+    }
+
+    /// Forward only if `self` does not match.
+    #[inline]
+    fn not(self) -> Not<Self> {
+        Not(self)
+    }
+}
+
+impl<N: Next> NextExt for N {}
+
+/// Forwards when both inner [`Next`] implementations match.
+pub struct And<A, B>(pub A, pub B);
+
+impl<A: Next, B: Next> Next for And<A, B> {
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        self.0.next(res) && self.1.next(res)
+    }
+}
+
+/// Forwards when either inner [`Next`] implementation matches.
+pub struct Or<A, B>(pub A, pub B);
+
+impl<A: Next, B: Next> Next for Or<A, B> {
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        self.0.next(res) || self.1.next(res)
+    }
+}
+
+/// Forwards when the inner [`Next`] implementation does not match.
+pub struct Not<N>(pub N);
+
+impl<N: Next> Next for Not<N> {
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        !self.0.next(res)
+    }
+}
+
 /// Simple [`StatusCode`] response guard.
 ///
 /// Blocks the response the specified status-code is present.
@@ -71,3 +175,213 @@ impl Next for HasHeader {
         res.headers().contains_key(&self.0)
     }
 }
+
+/// Forward [`Next`] on a range of status codes.
+///
+/// Blocks the response if the status-code is within the inclusive range.
+pub struct IsStatusRange(pub RangeInclusive<u16>);
+
+impl IsStatusRange {
+    #[inline]
+    pub fn new(start: StatusCode, end: StatusCode) -> Self {
+        Self(start.as_u16()..=end.as_u16())
+    }
+}
+
+impl From<RangeInclusive<StatusCode>> for IsStatusRange {
+    #[inline]
+    fn from(value: RangeInclusive<StatusCode>) -> Self {
+        Self::new(*value.start(), *value.end())
+    }
+}
+
+impl Next for IsStatusRange {
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        self.0.contains(&res.status().as_u16())
+    }
+}
+
+/// Simple `4xx` client-error response guard.
+///
+/// Blocks the response if the status-code is a client error.
+pub struct IsClientError;
+
+impl Next for IsClientError {
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        res.status().is_client_error()
+    }
+}
+
+/// Simple `5xx` server-error response guard.
+///
+/// Blocks the response if the status-code is a server error.
+pub struct IsServerError;
+
+impl Next for IsServerError {
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        res.status().is_server_error()
+    }
+}
+
+/// Exact [`HeaderValue`] response guard.
+///
+/// Blocks the response if the specified header has the given value.
+pub struct HeaderEquals(pub HeaderName, pub HeaderValue);
+
+impl HeaderEquals {
+    pub fn new(name: HeaderName, value: HeaderValue) -> Self {
+        Self(name, value)
+    }
+}
+
+impl Next for HeaderEquals {
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        res.headers().get_all(&self.0).any(|v| v == self.1)
+    }
+}
+
+/// Regex [`HeaderValue`] response guard.
+///
+/// Blocks the response if the specified header value matches the pattern.
+pub struct HeaderMatches(pub HeaderName, pub Regex);
+
+impl HeaderMatches {
+    pub fn new(name: HeaderName, pattern: Regex) -> Self {
+        Self(name, pattern)
+    }
+}
+
+impl Next for HeaderMatches {
+    #[inline]
+    fn next(&self, res: &HttpResponse) -> bool {
+        res.headers()
+            .get_all(&self.0)
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| self.1.is_match(v))
+    }
+}
+
+/// Simple [`Mime`] content-type response guard.
+///
+/// Blocks the response if the `Content-Type` matches the specified
+/// type and subtype. A `*` subtype matches any subtype,
+/// and parameters such as `charset` are ignored.
+pub struct IsContentType(pub Mime);
+
+impl IsContentType {
+    pub fn new(mime: Mime) -> Self {
+        Self(mime)
+    }
+}
+
+impl From<Mime> for IsContentType {
+    #[inline]
+    fn from(value: Mime) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Next for IsContentType {
+    fn next(&self, res: &HttpResponse) -> bool {
+        let Some(mime) = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<Mime>().ok())
+        else {
+            return false;
+        };
+        mime.type_() == self.0.type_()
+            && (self.0.subtype() == "*" || mime.subtype() == self.0.subtype())
+    }
+}
+
+/// Async response body equivalent of [`Next`].
+///
+/// Inspects the buffered response body in addition to the response head,
+/// and forwards the request to the next [`Link`](crate::Link) when matched.
+/// See [`Link::next_body`](crate::Link::next_body) for more details.
+///
+/// Implemented for any `Fn(&HttpResponse, &Bytes) -> bool` closure.
+///
+/// # Examples
+/// ```
+/// use actix_web::{HttpResponse, web::{self, Bytes}};
+/// use actix_chain::Link;
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// Link::new(web::get().to(index))
+///     .next_body(|_: &HttpResponse, body: &Bytes| body.is_empty());
+/// ```
+pub trait NextBody {
+    fn next<'a>(&'a self, res: &'a HttpResponse, body: &'a Bytes) -> LocalBoxFuture<'a, bool>;
+}
+
+impl<F> NextBody for F
+where
+    F: Fn(&HttpResponse, &Bytes) -> bool,
+{
+    #[inline]
+    fn next<'a>(&'a self, res: &'a HttpResponse, body: &'a Bytes) -> LocalBoxFuture<'a, bool> {
+        Box::pin(ready((self)(res, body)))
+    }
+}
+
+/// Regex response body guard.
+///
+/// Blocks the response if the body is valid UTF-8 and matches the pattern.
+pub struct BodyMatches(pub Regex);
+
+impl BodyMatches {
+    pub fn new(pattern: Regex) -> Self {
+        Self(pattern)
+    }
+}
+
+impl NextBody for BodyMatches {
+    fn next<'a>(&'a self, _: &'a HttpResponse, body: &'a Bytes) -> LocalBoxFuture<'a, bool> {
+        let matched = std::str::from_utf8(body).is_ok_and(|body| self.0.is_match(body));
+        Box::pin(ready(matched))
+    }
+}
+
+/// JSON response body guard.
+///
+/// Blocks the response if the body is valid JSON and the value at the
+/// [RFC 6901](https://datatracker.ietf.org/doc/html/rfc6901) pointer
+/// equals the specified value.
+///
+/// # Examples
+/// ```
+/// use actix_web::web;
+/// use actix_chain::{Link, next::JsonPointer};
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// Link::new(web::get().to(index))
+///     .next_body(JsonPointer::new("/error", "not_found"));
+/// ```
+pub struct JsonPointer(pub String, pub Value);
+
+impl JsonPointer {
+    pub fn new<P: Into<String>, V: Into<Value>>(pointer: P, value: V) -> Self {
+        Self(pointer.into(), value.into())
+    }
+}
+
+impl NextBody for JsonPointer {
+    fn next<'a>(&'a self, _: &'a HttpResponse, body: &'a Bytes) -> LocalBoxFuture<'a, bool> {
+        let matched = serde_json::from_slice::<Value>(body)
+            .is_ok_and(|json| json.pointer(&self.0) == Some(&self.1));
+        Box::pin(ready(matched))
+    }
+}
@@ -1,31 +1,50 @@
 use std::{
     cell::{RefCell, RefMut},
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io,
+    path::PathBuf,
     pin::Pin,
     rc::Rc,
-    task::{Context, Poll},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll, Waker},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use actix_web::{
     dev::Payload,
     error::PayloadError,
+    rt::{
+        self,
+        task::{JoinHandle, spawn_blocking},
+    },
     web::{Bytes, BytesMut},
 };
 use futures_core::{Stream, stream::LocalBoxStream};
 
+/// Max chunk size read back from the spill file during replay
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
 pub(crate) struct PayloadRef(Rc<RefCell<PayloadBuffer>>);
 
 impl PayloadRef {
-    pub fn new<S>(stream: S, buffer_size: usize) -> Self
+    pub fn new<S>(stream: S, buffer_size: usize, file_size: usize) -> Self
     where
         S: Stream<Item = Result<Bytes, PayloadError>> + 'static,
     {
         Self::from(PayloadBuffer {
             stream: Box::pin(stream),
             buf: BytesMut::with_capacity(1_024), // pre-allocate 1KiB
+            file: None,
             eof: false,
             overflow: false,
-            cursor: 0,
+            failed: false,
+            waiters: Vec::new(),
             body_buffer_size: buffer_size,
+            body_file_size: file_size,
         })
     }
 
@@ -34,9 +53,21 @@ impl PayloadRef {
         self.0.borrow_mut()
     }
 
+    /// Create a new independent reader starting at the beginning of the payload
     #[inline]
     pub fn stream(&self) -> LocalBoxStream<'static, Result<Bytes, PayloadError>> {
-        Box::pin(self.clone())
+        Box::pin(PayloadReader {
+            buf: self.clone(),
+            cursor: 0,
+            reading: None,
+        })
+    }
+
+    /// Check if the payload can be read again from the beginning
+    #[inline]
+    pub fn replayable(&self) -> bool {
+        let this = self.0.borrow();
+        !this.overflow && !this.failed
     }
 
     pub fn payload(&self) -> Payload {
@@ -58,71 +89,260 @@ impl Clone for PayloadRef {
     }
 }
 
-//TODO: implement max-body-size which writes to file buffer
-// to support beyond memory-limit
+/// Temporary file used to store payload data beyond the memory limit.
+///
+/// The file is only accessible by its owner and is removed from disk once
+/// dropped. Reads and writes run on the blocking thread pool so they never
+/// stall the worker thread.
+pub(crate) struct SpillFile {
+    path: PathBuf,
+    file: Arc<File>,
+    /// Number of bytes written to the file
+    len: usize,
+    /// Whether a write is currently running
+    writing: bool,
+}
+
+impl SpillFile {
+    fn new() -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let name = format!(
+            "actix-chain-{}-{nanos}-{}.body",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(&path)?;
+        Ok(Self {
+            path,
+            file: Arc::new(file),
+            len: 0,
+            writing: false,
+        })
+    }
+
+    /// Append the data in the background, waking any waiting readers
+    /// of the owning buffer once it can be read back
+    fn write(&mut self, data: Bytes, owner: PayloadRef) {
+        let file = Arc::clone(&self.file);
+        let pos = self.len as u64;
+        self.writing = true;
+        rt::spawn(async move {
+            let len = data.len();
+            let result = spawn_blocking(move || write_all_at(&file, &data, pos)).await;
+            let mut this = owner.get_mut();
+            match result.unwrap_or_else(|err| Err(io::Error::other(err))) {
+                Ok(()) => {
+                    if let Some(file) = this.file.as_mut() {
+                        file.len += len;
+                        file.writing = false;
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("failed to write payload buffer: {err:?}");
+                    this.failed = true;
+                }
+            }
+            this.wake_waiters();
+        });
+    }
+
+    /// Read the chunk starting at the given position in the background
+    fn read_at(&self, pos: usize) -> JoinHandle<io::Result<Bytes>> {
+        let size = (self.len - pos).min(FILE_CHUNK_SIZE);
+        let file = Arc::clone(&self.file);
+        spawn_blocking(move || {
+            let mut data = vec![0; size];
+            read_exact_at(&file, &mut data, pos as u64)?;
+            Ok(Bytes::from(data))
+        })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            tracing::warn!("failed to remove payload buffer {:?}: {err:?}", self.path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], pos: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, data, pos)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, data: &mut [u8], pos: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, data, pos)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut data: &[u8], mut pos: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        match file.seek_write(data, pos)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => (data, pos) = (&data[n..], pos + n as u64),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut data: &mut [u8], mut pos: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        match file.seek_read(data, pos)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => (data, pos) = (&mut data[n..], pos + n as u64),
+        }
+    }
+    Ok(())
+}
 
 /// Payload buffer.
+///
+/// Keeps up to `body_buffer_size` bytes in memory and spills up to
+/// `body_file_size` additional bytes into a temporary file so the
+/// payload can be replayed by later links, or read by several links
+/// at once.
 pub(crate) struct PayloadBuffer {
     pub(crate) stream: LocalBoxStream<'static, Result<Bytes, PayloadError>>,
     pub(crate) buf: BytesMut,
+    pub(crate) file: Option<SpillFile>,
 
     pub(crate) eof: bool,
     pub(crate) overflow: bool,
+    pub(crate) failed: bool,
+    pub(crate) waiters: Vec<Waker>,
 
-    pub(crate) cursor: usize,
     pub(crate) body_buffer_size: usize,
+    pub(crate) body_file_size: usize,
 }
 
 impl PayloadBuffer {
+    /// Total number of bytes buffered in memory and on disk
     #[inline]
-    pub(crate) fn reset_stream(&mut self) {
-        self.cursor = 0;
+    fn buffered(&self) -> usize {
+        self.buf.len() + self.file.as_ref().map(|f| f.len).unwrap_or(0)
     }
 
-    #[inline]
-    fn read_buffered(&mut self) -> Option<Bytes> {
-        if self.cursor < self.buf.len() {
-            let data = self
-                .buf
-                .clone()
-                .split_to(self.buf.len() - self.cursor)
-                .freeze();
-            self.cursor += data.len();
-            return Some(data);
+    /// Wake any other readers waiting on the underlying stream
+    fn wake_waiters(&mut self) {
+        self.waiters.drain(..).for_each(Waker::wake);
+    }
+
+    /// Store newly received data in memory or the spill file
+    fn store(&mut self, data: &Bytes, owner: &PayloadRef) -> Result<(), PayloadError> {
+        if self.file.is_none() && self.buf.len() + data.len() <= self.body_buffer_size {
+            self.buf.extend_from_slice(data);
+            return Ok(());
+        }
+        if self.buffered() + data.len() > self.body_buffer_size + self.body_file_size {
+            return Err(PayloadError::Overflow);
         }
-        None
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => {
+                tracing::debug!("payload exceeded memory buffer, spilling to disk");
+                self.file.insert(SpillFile::new()?)
+            }
+        };
+        file.write(data.clone(), owner.clone());
+        Ok(())
     }
 }
 
-impl Stream for PayloadRef {
+/// Independent reader over a shared [`PayloadBuffer`].
+struct PayloadReader {
+    buf: PayloadRef,
+    cursor: usize,
+    /// Pending read of the spill file
+    reading: Option<JoinHandle<io::Result<Bytes>>>,
+}
+
+impl Stream for PayloadReader {
     type Item = Result<Bytes, PayloadError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut this = self.get_mut().get_mut();
-        if let Some(data) = this.read_buffered() {
-            return Poll::Ready(Some(Ok(data)));
-        }
-        if this.eof {
-            return Poll::Ready(None);
-        }
-        if this.overflow {
-            return Poll::Ready(Some(Err(PayloadError::Overflow)));
-        }
-        match Pin::new(&mut this.stream).poll_next(cx) {
-            Poll::Ready(Some(Ok(data))) => {
-                if this.cursor + data.len() > this.body_buffer_size {
-                    this.overflow = true;
-                    return Poll::Ready(Some(Err(PayloadError::Overflow)));
+        let reader = self.get_mut();
+        loop {
+            if let Some(read) = reader.reading.as_mut() {
+                let result = match Pin::new(read).poll(cx) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => return Poll::Pending,
+                };
+                reader.reading = None;
+                let data = result.unwrap_or_else(|err| Err(io::Error::other(err)));
+                if let Ok(data) = data.as_ref() {
+                    reader.cursor += data.len();
                 }
-                this.buf.extend_from_slice(&data);
-                this.cursor += data.len();
-                Poll::Ready(Some(Ok(data)))
+                return Poll::Ready(Some(data.map_err(PayloadError::Io)));
+            }
+
+            let mut this = reader.buf.get_mut();
+            if reader.cursor < this.buf.len() {
+                let data = Bytes::copy_from_slice(&this.buf[reader.cursor..]);
+                reader.cursor += data.len();
+                return Poll::Ready(Some(Ok(data)));
+            }
+            let pos = reader.cursor - this.buf.len();
+            if let Some(file) = this.file.as_ref().filter(|f| pos < f.len) {
+                reader.reading = Some(file.read_at(pos));
+                continue;
+            }
+            if this.failed {
+                let err = io::Error::other("failed to buffer request payload");
+                return Poll::Ready(Some(Err(PayloadError::Io(err))));
             }
-            Poll::Ready(None) => {
-                this.eof = true;
-                Poll::Ready(None)
+            if this.eof {
+                return Poll::Ready(None);
             }
-            status => status,
+            if this.overflow {
+                return Poll::Ready(Some(Err(PayloadError::Overflow)));
+            }
+            // wait for the running write before reading more of the stream
+            if this.file.as_ref().is_some_and(|f| f.writing) {
+                if !this.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                    this.waiters.push(cx.waker().clone());
+                }
+                return Poll::Pending;
+            }
+            return match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(data))) => {
+                    this.wake_waiters();
+                    if let Err(err) = this.store(&data, &reader.buf) {
+                        this.overflow = true;
+                        return Poll::Ready(Some(Err(err)));
+                    }
+                    reader.cursor += data.len();
+                    Poll::Ready(Some(Ok(data)))
+                }
+                Poll::Ready(None) => {
+                    this.wake_waiters();
+                    this.eof = true;
+                    Poll::Ready(None)
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    this.wake_waiters();
+                    Poll::Ready(Some(Err(err)))
+                }
+                Poll::Pending => {
+                    if !this.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                        this.waiters.push(cx.waker().clone());
+                    }
+                    Poll::Pending
+                }
+            };
         }
     }
 }
@@ -0,0 +1,72 @@
+//! Details of responses discarded by the [`Chain`](crate::Chain)
+
+use std::rc::Rc;
+
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    http::{StatusCode, header::HeaderMap},
+    web::Bytes,
+};
+
+/// Response discarded by the previous [`Link`](crate::Link) in the chain.
+///
+/// When a link's response matches its [`Next`](crate::next::Next) criteria,
+/// the response details are attached to the request extensions before
+/// the request is forwarded to the next link, or to the chain's
+/// [`default_service`](crate::Chain::default_service). Only the most
+/// recently discarded response is kept.
+///
+/// **IMPORTANT:** Responses are only attached when using
+/// [`Execution::Sequential`](crate::Execution::Sequential).
+///
+/// # Examples
+/// ```
+/// use actix_web::{HttpResponse, Responder, web};
+/// use actix_chain::{Chain, Link, PreviousResponse};
+///
+/// async fn upstream() -> HttpResponse {
+///     HttpResponse::NotFound().body("missing")
+/// }
+///
+/// async fn fallback(previous: Option<web::ReqData<PreviousResponse>>) -> impl Responder {
+///     match previous {
+///         Some(previous) => format!("upstream returned {}", previous.status),
+///         None => "upstream unavailable".to_owned(),
+///     }
+/// }
+///
+/// Chain::default()
+///     .link(Link::new(web::get().to(upstream)))
+///     .link(Link::new(web::get().to(fallback)));
+/// ```
+#[derive(Clone, Debug)]
+pub struct PreviousResponse {
+    /// Position of the link within the chain.
+    pub index: usize,
+    /// Name assigned with [`Link::name`](crate::Link::name).
+    pub name: Option<Rc<str>>,
+    /// Status of the discarded response.
+    pub status: StatusCode,
+    /// Headers of the discarded response.
+    pub headers: HeaderMap,
+    /// Body of the discarded response.
+    ///
+    /// Only available when the body was already held in memory, such as
+    /// fixed bodies or bodies buffered by
+    /// [`Link::next_body`](crate::Link::next_body).
+    pub body: Option<Bytes>,
+}
+
+impl PreviousResponse {
+    pub(crate) fn new(index: usize, name: Option<Rc<str>>, res: HttpResponse) -> Self {
+        let (res, body) = res.into_parts();
+        Self {
+            index,
+            name,
+            status: res.status(),
+            headers: res.headers().clone(),
+            body: body.try_into_bytes().ok(),
+        }
+    }
+}
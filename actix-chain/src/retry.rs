@@ -0,0 +1,163 @@
+//! Retry policy for [`Link::retry`](crate::Link::retry)
+
+use std::{rc::Rc, time::Duration};
+
+use actix_web::{
+    HttpResponse,
+    http::{Method, StatusCode},
+};
+
+use crate::next::{IsStatus, Next};
+
+/// Retry policy for transient failures of a [`Link`](crate::Link).
+///
+/// Responses matching the retry criteria are discarded and the request is
+/// sent to the same link again after an exponential backoff, replaying the
+/// buffered request body. Once all attempts are used, the final response is
+/// evaluated by the link's [`Next`] criteria as normal.
+///
+/// Error responses are retried like any other response, unless the link
+/// forwards them with [`OnError::Next`](crate::OnError::Next). Errors
+/// returned by the service are never retried.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+///
+/// use actix_web::{http::StatusCode, web};
+/// use actix_chain::{Link, Retry, next::IsStatus};
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// Link::new(web::get().to(index))
+///     .retry(Retry::new(3)
+///         .backoff(Duration::from_millis(50))
+///         .max_backoff(Duration::from_secs(1))
+///         .retry_on(IsStatus(StatusCode::GATEWAY_TIMEOUT)));
+/// ```
+#[derive(Clone)]
+pub struct Retry {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    all_methods: bool,
+    retry_on: Vec<Rc<dyn Next>>,
+}
+
+impl Retry {
+    /// Creates a new `Retry` policy which calls the link at most
+    /// `attempts` times, including the first attempt.
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+            all_methods: false,
+            retry_on: Vec::new(),
+        }
+    }
+
+    /// Set the delay before the first retry, which doubles after every
+    /// following attempt.
+    ///
+    /// Default is 100ms.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set the maximum delay between attempts.
+    ///
+    /// Default is 5s.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Randomize each delay between half and all of the computed backoff.
+    ///
+    /// Default is true.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Allow retrying requests of any method.
+    ///
+    /// By default only idempotent methods (`GET`, `HEAD`, `OPTIONS`,
+    /// `PUT`, `DELETE` and `TRACE`) are retried.
+    pub fn all_methods(mut self, all_methods: bool) -> Self {
+        self.all_methods = all_methods;
+        self
+    }
+
+    /// Configure which responses should be retried.
+    ///
+    /// Calling this method multiple times retries when any of the supplied
+    /// criteria match. The default is to retry "502 Bad Gateway" and
+    /// "503 Service Unavailable" responses only.
+    pub fn retry_on<N>(mut self, next: N) -> Self
+    where
+        N: Next + 'static,
+    {
+        self.retry_on.push(Rc::new(next));
+        self
+    }
+
+    /// Convert public [`Retry`] builder into [`RetryInner`]
+    pub(crate) fn inner(&self) -> RetryInner {
+        let retry_on: Vec<Rc<dyn Next>> = match self.retry_on.is_empty() {
+            true => vec![
+                IsStatus::rc(StatusCode::BAD_GATEWAY),
+                IsStatus::rc(StatusCode::SERVICE_UNAVAILABLE),
+            ],
+            false => self.retry_on.clone(),
+        };
+        RetryInner {
+            attempts: self.attempts,
+            backoff: self.backoff,
+            max_backoff: self.max_backoff,
+            jitter: self.jitter,
+            all_methods: self.all_methods,
+            retry_on,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RetryInner {
+    pub(crate) attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    all_methods: bool,
+    retry_on: Vec<Rc<dyn Next>>,
+}
+
+impl RetryInner {
+    /// Check if requests with the given method may be retried
+    #[inline]
+    pub(crate) fn allows(&self, method: &Method) -> bool {
+        self.all_methods || method.is_idempotent()
+    }
+
+    /// Check if the response should be retried
+    #[inline]
+    pub(crate) fn retryable(&self, res: &HttpResponse) -> bool {
+        self.retry_on.iter().any(|next| next.next(res))
+    }
+
+    /// Delay before the given retry, starting at 1
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self.backoff.saturating_mul(factor).min(self.max_backoff);
+        if !self.jitter {
+            return delay;
+        }
+        delay.mul_f64(0.5 + rand::random::<f64>() / 2.0)
+    }
+}
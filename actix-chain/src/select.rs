@@ -0,0 +1,110 @@
+//! Link selection strategies for [`Chain::selection`](crate::Chain::selection)
+
+use std::{
+    cell::Cell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use actix_web::{dev::ServiceRequest, http::header};
+
+use crate::link::LinkInner;
+
+/// Strategy used to choose which matching [`Link`](crate::Link)
+/// handles a request first.
+///
+/// The chosen link is always called first, while the remaining links
+/// keep their original order and still act as fallbacks whenever the
+/// [`Next`](crate::next::Next) criteria of a link is met.
+///
+/// Links are chosen according to their [`Link::weight`](crate::Link::weight).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Selection {
+    /// Always try links in the order they were added.
+    #[default]
+    Sequential,
+    /// Rotate between links, proportionally to their weights.
+    RoundRobin,
+    /// Choose a random link, proportionally to their weights.
+    WeightedRandom,
+    /// Choose a link based on a hash of the client peer IP address.
+    StickyIp,
+    /// Choose a link based on a hash of the named cookie's value.
+    ///
+    /// Requests without the cookie fall back to [`Selection::WeightedRandom`].
+    StickyCookie(String),
+}
+
+/// Per-worker selection state.
+#[derive(Default)]
+pub(crate) struct Selector {
+    counter: Cell<u64>,
+}
+
+#[inline]
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Find value of the specified cookie within the request headers
+fn find_cookie<'a>(req: &'a ServiceRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+impl Selector {
+    fn seed(&self, selection: &Selection, req: &ServiceRequest) -> Option<u64> {
+        match selection {
+            Selection::Sequential => None,
+            Selection::RoundRobin => {
+                let count = self.counter.get();
+                self.counter.set(count.wrapping_add(1));
+                Some(count)
+            }
+            Selection::WeightedRandom => Some(rand::random()),
+            Selection::StickyIp => Some(match req.peer_addr() {
+                Some(addr) => hash(&addr.ip()),
+                None => rand::random(),
+            }),
+            Selection::StickyCookie(name) => Some(match find_cookie(req, name) {
+                Some(value) => hash(value),
+                None => rand::random(),
+            }),
+        }
+    }
+
+    /// Reorder matching links so the selected link is called first
+    pub(crate) fn order(
+        &self,
+        selection: &Selection,
+        req: &ServiceRequest,
+        links: &mut [(usize, &LinkInner)],
+    ) {
+        let total: u64 = links.iter().map(|(_, link)| link.weight as u64).sum();
+        if total == 0 || links.len() < 2 {
+            return;
+        }
+        let Some(seed) = self.seed(selection, req) else {
+            return;
+        };
+        let mut pos = seed % total;
+        let chosen = links
+            .iter()
+            .position(|(_, link)| match pos.checked_sub(link.weight as u64) {
+                Some(rem) => {
+                    pos = rem;
+                    false
+                }
+                None => true,
+            })
+            .unwrap_or_default();
+        links[..=chosen].rotate_right(1);
+    }
+}
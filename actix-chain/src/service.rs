@@ -1,16 +1,29 @@
-use std::{ops::Deref, rc::Rc};
+use std::{
+    cell::RefCell,
+    ops::Deref,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use actix_service::boxed::{BoxService, BoxServiceFactory};
 use actix_web::{
     HttpMessage,
     body::BoxBody,
-    dev::{self, Service, ServiceRequest, ServiceResponse},
+    dev::{self, Extensions, Service, ServiceRequest, ServiceResponse, Url},
     error::Error,
+    http::header::{HeaderName, HeaderValue},
 };
 use futures_core::future::LocalBoxFuture;
 
-use crate::link::{LinkInner, default_response};
+use crate::concurrent::Concurrent;
+use crate::copy::RequestCopy;
+use crate::factory::{Execution, Exhausted};
+use crate::handle::DynamicLinks;
+use crate::link::{LinkInner, Outcome, default_response};
+use crate::mirror::MirrorInner;
 use crate::payload::PayloadRef;
+use crate::select::{Selection, Selector};
+use crate::trace::ChainTrace;
 
 pub type HttpService = BoxService<ServiceRequest, ServiceResponse, Error>;
 pub type HttpNewService = BoxServiceFactory<(), ServiceRequest, ServiceResponse, Error, ()>;
@@ -28,8 +41,205 @@ impl Deref for ChainService {
 }
 
 pub struct ChainInner {
-    pub(crate) links: Vec<LinkInner>,
+    pub(crate) links: RefCell<Rc<Vec<LinkInner>>>,
+    pub(crate) dynamic: Option<DynamicLinks>,
     pub(crate) body_buffer_size: usize,
+    pub(crate) body_file_size: usize,
+    pub(crate) execution: Execution,
+    pub(crate) selection: Selection,
+    pub(crate) selector: Selector,
+    pub(crate) mirrors: Vec<Rc<MirrorInner>>,
+    pub(crate) default: Option<Rc<HttpService>>,
+    pub(crate) exhausted: Exhausted,
+    pub(crate) debug_header: Option<HeaderName>,
+    pub(crate) data: Option<Rc<Extensions>>,
+}
+
+impl ChainInner {
+    /// Call the default service, or respond with "404 Not Found"
+    async fn fallback(&self, req: ServiceRequest) -> Result<ServiceResponse, Error> {
+        match self.default.as_ref() {
+            Some(service) => service.call(req).await,
+            None => Ok(default_response(req)),
+        }
+    }
+
+    /// Retrieve the current links, rebuilding them if the handle was updated
+    async fn current_links(&self) -> Rc<Vec<LinkInner>> {
+        if let Some(dynamic) = self.dynamic.as_ref()
+            && dynamic.is_stale()
+            && let Ok(links) = dynamic.build().await
+        {
+            *self.links.borrow_mut() = Rc::new(links);
+        }
+        Rc::clone(&self.links.borrow())
+    }
+
+    /// Call the chain's only link without buffering the request payload
+    async fn call_once(
+        &self,
+        link: &LinkInner,
+        mut req: ServiceRequest,
+        trace: &mut ChainTrace,
+    ) -> Result<ServiceResponse, Error> {
+        let url = Url::new(req.uri().clone());
+        if !link.matches(url.path(), &req.guard_ctx()) {
+            return self.fallback(req).await;
+        }
+        link.apply(&mut req);
+        let start = Instant::now();
+        let result = link.call(req, None).await;
+        link.trace(trace, &result, start.elapsed());
+        match result? {
+            Outcome::Response(res) | Outcome::Rejected(res) => {
+                trace.serve_last();
+                Ok(res)
+            }
+            Outcome::Next(http_req) => self.fallback(ServiceRequest::from_request(http_req)).await,
+        }
+    }
+
+    /// Buffer the request payload and call the chain's links and mirrors
+    async fn call_buffered(
+        &self,
+        links: &[LinkInner],
+        mut req: ServiceRequest,
+        trace: &mut ChainTrace,
+    ) -> Result<ServiceResponse, Error> {
+        let payload = req.take_payload();
+        let buf = PayloadRef::new(payload, self.body_buffer_size, self.body_file_size);
+        req.set_payload(buf.payload());
+
+        let mirrors: Vec<_> = self
+            .mirrors
+            .iter()
+            .filter_map(|mirror| mirror.prepare(&req, &buf, self.data.as_ref()))
+            .collect();
+        let res = self.call_links(links, req, buf, trace).await;
+        let status = res.as_ref().ok().map(|res| res.status());
+        mirrors.into_iter().for_each(|mirror| mirror.spawn(status));
+        res
+    }
+
+    /// Attach the chain trace to the response, replacing the trace of any
+    /// nested chain which is already recorded within the link attempt
+    fn finish(&self, mut res: ServiceResponse, trace: ChainTrace) -> ServiceResponse {
+        if let Some(name) = self.debug_header.as_ref()
+            && let Ok(value) = HeaderValue::from_str(&trace.to_string())
+        {
+            res.headers_mut().insert(name.clone(), value);
+        }
+        res.response_mut().extensions_mut().insert(trace);
+        res
+    }
+
+    /// Call the matching links concurrently, starting each link after
+    /// the `delay` if specified.
+    ///
+    /// See [`Concurrent`] for how the served response is chosen.
+    async fn call_concurrent(
+        &self,
+        req: ServiceRequest,
+        buf: PayloadRef,
+        links: Vec<(usize, &LinkInner)>,
+        delay: Option<Duration>,
+        trace: &mut ChainTrace,
+    ) -> Result<ServiceResponse, Error> {
+        let template = RequestCopy::new(req.request(), self.data.as_ref());
+        let mut concurrent =
+            Concurrent::new(req, links, delay, self.exhausted, trace, &template, &buf);
+        if let Some(res) = (&mut concurrent).await {
+            return res;
+        }
+        let req = concurrent.into_request().await?;
+        self.fallback(req).await
+    }
+
+    /// Call the matching links according to the chain's execution strategy
+    async fn call_links(
+        &self,
+        links: &[LinkInner],
+        mut req: ServiceRequest,
+        buf: PayloadRef,
+        trace: &mut ChainTrace,
+    ) -> Result<ServiceResponse, Error> {
+        let ctx = req.guard_ctx();
+        let url = Url::new(req.uri().clone());
+        let mut active_links: Vec<_> = links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.matches(url.path(), &ctx) && link.is_available())
+            .collect();
+        self.selector
+            .order(&self.selection, &req, &mut active_links);
+
+        let addr = req
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        tracing::debug!(
+            "{addr} {}/{} links matched {:?} {:?}",
+            active_links.len(),
+            links.len(),
+            req.method(),
+            req.uri()
+        );
+
+        let delay = match self.execution {
+            Execution::Sequential => None,
+            Execution::Parallel => Some(None),
+            Execution::Hedged(delay) => Some(Some(delay)),
+        };
+        if let Some(delay) = delay {
+            return self
+                .call_concurrent(req, buf, active_links, delay, trace)
+                .await;
+        }
+
+        let mut link_iter = active_links.into_iter().peekable();
+        while let Some((_, link)) = link_iter.next() {
+            tracing::debug!("{addr} calling link {link}");
+            let original = link.apply(&mut req);
+            if let Some((uri, _)) = original.as_ref() {
+                tracing::debug!("{addr} updated uri {uri:?} -> {:?}", req.uri());
+            }
+
+            // skip evaluating the last response when it is always returned
+            let last = link_iter.peek().is_none() && self.exhausted == Exhausted::LastResponse;
+            let start = Instant::now();
+            let result = match last {
+                true => link.call(req, Some(&buf)).await,
+                false => link.call_next(req, &buf).await,
+            };
+            link.trace(trace, &result, start.elapsed());
+            let http_req = match result? {
+                Outcome::Response(res) => {
+                    tracing::debug!("{addr} link {link} response={:?}", res.status());
+                    trace.serve_last();
+                    return Ok(res);
+                }
+                Outcome::Rejected(res) => {
+                    tracing::debug!("{addr} link {link} response={:?}", res.status());
+                    let (http_req, http_res) = res.into_parts();
+                    http_req.extensions_mut().insert(link.previous(http_res));
+                    http_req
+                }
+                Outcome::Next(http_req) => {
+                    tracing::debug!("{addr} link {link} failed, continuing");
+                    http_req
+                }
+            };
+
+            req = ServiceRequest::from_parts(http_req, buf.payload());
+
+            if let Some((uri, info)) = original {
+                req.head_mut().uri = uri;
+                *req.match_info_mut() = info;
+            }
+        }
+
+        self.fallback(req).await
+    }
 }
 
 impl Service<ServiceRequest> for ChainService {
@@ -40,62 +250,23 @@ impl Service<ServiceRequest> for ChainService {
     dev::always_ready!();
 
     fn call(&self, mut req: ServiceRequest) -> Self::Future {
-        let this = self.clone();
-        if self.links.len() == 1 {
-            return Box::pin(async move { this.links[0].call_once(req).await });
+        if let Some(data) = self.data.as_ref() {
+            req.add_data_container(Rc::clone(data));
         }
-
+        let this = self.clone();
         Box::pin(async move {
-            let payload = req.take_payload();
-            let buf = PayloadRef::new(payload, this.body_buffer_size);
-            req.set_payload(buf.payload());
-
-            let ctx = req.guard_ctx();
-            let active_links: Vec<_> = this
-                .links
-                .iter()
-                .enumerate()
-                .filter(|(_, link)| link.matches(req.uri().path(), &ctx))
-                .collect();
-
-            let addr = req
-                .peer_addr()
-                .map(|addr| addr.to_string())
-                .unwrap_or_default();
-            tracing::debug!(
-                "{addr} {}/{} links matched {:?} {:?}",
-                active_links.len(),
-                this.links.len(),
-                req.method(),
-                req.uri()
-            );
-
-            let mut link_iter = active_links.into_iter().peekable();
-            while let Some((n, link)) = link_iter.next() {
-                tracing::debug!("{addr} calling link {n}");
-                let mut original_uri = None;
-                if let Some(uri) = link.new_uri(req.uri()) {
-                    original_uri = Some(req.uri().clone());
-                    tracing::debug!("{addr} updated uri {:?} -> {uri:?}", req.uri());
-                    req.head_mut().uri = uri;
-                }
-
-                let res = link.service.call(req).await?;
-                let (http_req, http_res) = res.into_parts();
-                tracing::debug!("{addr} link {n} response={:?}", http_res.status());
-                if link_iter.peek().is_none() || !link.go_next(&http_res) {
-                    return Ok(ServiceResponse::new(http_req, http_res));
-                }
-
-                buf.get_mut().reset_stream();
-                req = ServiceRequest::from_parts(http_req, buf.payload());
-
-                if let Some(uri) = original_uri {
-                    req.head_mut().uri = uri;
-                }
-            }
+            let links = this.current_links().await;
+            let once = links.len() == 1
+                && links[0].retry.is_none()
+                && this.mirrors.is_empty()
+                && this.exhausted == Exhausted::LastResponse;
 
-            Ok(default_response(req))
+            let mut trace = ChainTrace::default();
+            let res = match once {
+                true => this.call_once(&links[0], req, &mut trace).await,
+                false => this.call_buffered(&links, req, &mut trace).await,
+            };
+            res.map(|res| this.finish(res, trace))
         })
     }
 }
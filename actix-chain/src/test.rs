@@ -0,0 +1,42 @@
+//! Helpers for testing [`Chain`] routing.
+
+use actix_web::test::TestRequest;
+
+use crate::{Chain, explain::ChainExplain};
+
+/// Report how the chain routes the test request.
+///
+/// See [`Chain::explain`] for more details.
+pub fn explain(chain: &Chain, req: TestRequest) -> ChainExplain {
+    chain.explain(&req.to_srv_request())
+}
+
+/// Assert that the test request is routed through the given links,
+/// listing the first matching link at every level of nested chains.
+///
+/// Links are listed by name if assigned, or by index otherwise.
+/// The full [`ChainExplain`] report is included on failure.
+///
+/// # Examples
+/// ```
+/// use actix_web::{test::TestRequest, web};
+/// use actix_chain::{Chain, Link, test::assert_route};
+///
+/// async fn index() -> &'static str {
+///     "Hello world!"
+/// }
+///
+/// let api = Chain::new("/api")
+///     .link(Link::new(web::get().to(index)).prefix("/users").name("users"));
+/// let chain = Chain::default()
+///     .link(Link::from(api).name("api"))
+///     .link(Link::new(web::get().to(index)).name("fallback"));
+///
+/// assert_route(&chain, TestRequest::with_uri("/api/users/1"), &["api", "users"]);
+/// assert_route(&chain, TestRequest::with_uri("/about"), &["fallback"]);
+/// ```
+#[track_caller]
+pub fn assert_route(chain: &Chain, req: TestRequest, route: &[&str]) {
+    let explain = explain(chain, req);
+    assert_eq!(explain.route(), route, "unexpected route\n{explain}");
+}
@@ -0,0 +1,120 @@
+//! Record of the links tried while serving a request
+
+use std::{fmt, rc::Rc, time::Duration};
+
+use actix_web::http::StatusCode;
+
+/// Details of a single [`Link`](crate::Link) called by a [`Chain`](crate::Chain).
+#[derive(Clone, Debug)]
+pub struct LinkAttempt {
+    /// Position of the link within the chain.
+    pub index: usize,
+    /// Name assigned with [`Link::name`](crate::Link::name).
+    pub name: Option<Rc<str>>,
+    /// Response status, or `None` if the link returned an error.
+    pub status: Option<StatusCode>,
+    /// Time spent waiting on the link's response.
+    pub elapsed: Duration,
+    /// Trace of the nested chain that produced the link's response,
+    /// such as a chain wrapped with [`Chain::wrap`](crate::Chain::wrap).
+    pub nested: Option<ChainTrace>,
+}
+
+/// Record of the links a [`Chain`](crate::Chain) tried for a request.
+///
+/// Stored in the response extensions of every chain response.
+///
+/// # Examples
+/// ```
+/// use actix_web::HttpResponse;
+/// use actix_chain::ChainTrace;
+///
+/// fn serving_link(res: &HttpResponse) -> Option<String> {
+///     let ext = res.extensions();
+///     let trace = ext.get::<ChainTrace>()?;
+///     trace.served().map(|attempt| attempt.to_string())
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ChainTrace {
+    /// Links called in the order their responses were received.
+    pub attempts: Vec<LinkAttempt>,
+    /// Position within `attempts` of the link that served the response,
+    /// or `None` if the response came from the chain's default service.
+    pub served: Option<usize>,
+}
+
+impl ChainTrace {
+    /// Retrieve the attempt of the link that served the response.
+    pub fn served(&self) -> Option<&LinkAttempt> {
+        self.attempts.get(self.served?)
+    }
+
+    /// List the serving link at every level of nested chains.
+    ///
+    /// Links are listed by name if assigned, or by index otherwise.
+    pub fn route(&self) -> Vec<String> {
+        let mut route = vec![];
+        let mut trace = Some(self);
+        while let Some(attempt) = trace.and_then(|trace| trace.served()) {
+            route.push(attempt.to_string());
+            trace = attempt.nested.as_ref();
+        }
+        route
+    }
+
+    #[inline]
+    pub(crate) fn record(
+        &mut self,
+        index: usize,
+        name: Option<Rc<str>>,
+        status: Option<StatusCode>,
+        elapsed: Duration,
+        nested: Option<ChainTrace>,
+    ) {
+        self.attempts.push(LinkAttempt {
+            index,
+            name,
+            status,
+            elapsed,
+            nested,
+        });
+    }
+
+    /// Mark the most recent attempt as the serving link
+    #[inline]
+    pub(crate) fn serve_last(&mut self) {
+        self.served = self.attempts.len().checked_sub(1);
+    }
+}
+
+impl fmt::Display for LinkAttempt {
+    /// Format the link name if assigned, or its index otherwise
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name.as_ref() {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "{}", self.index),
+        }
+    }
+}
+
+impl fmt::Display for ChainTrace {
+    /// Format the trace as `served=<link>[><nested link>...] tried=<link>:<status>:<ms>ms,...`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.served() {
+            Some(_) => write!(f, "served={}", self.route().join(">"))?,
+            None => write!(f, "served=default")?,
+        }
+        for (n, attempt) in self.attempts.iter().enumerate() {
+            let sep = if n == 0 { " tried=" } else { "," };
+            let status = attempt.status.map(|s| s.as_u16().to_string());
+            write!(
+                f,
+                "{sep}{attempt}:{}:{}ms",
+                status.as_deref().unwrap_or("error"),
+                attempt.elapsed.as_millis()
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,29 @@
-use actix_chain::{Chain, Link, next::IsStatus};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use actix_chain::{
+    Chain, ChainHandle, ChainTrace, CircuitBreaker, CircuitState, Execution, Exhausted, Link,
+    Mirror, MirrorStats, OnError, PreviousResponse, Retry, Selection,
+    next::{BodyMatches, HasHeader, IsServerError, IsStatus, JsonPointer, NextExt},
+    test::assert_route,
+};
+use actix_service::{fn_factory, fn_service};
 use actix_web::{
-    App, HttpRequest, HttpResponse, Responder,
-    http::StatusCode,
+    App, Error, HttpRequest, HttpResponse, Responder,
+    dev::{ServiceRequest, ServiceResponse},
+    error, guard,
+    http::{StatusCode, header::HeaderName},
     test::{self, TestRequest},
     web,
 };
+use regex_lite::Regex;
 
 mod common;
 
@@ -78,6 +97,34 @@ async fn test_prefix() {
     assert_eq!(common::get_body(res).await, "First link failed!");
 }
 
+#[actix_web::test]
+async fn test_guard() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(Link::new(web::get().to(fast)).guard(guard::Header("X-Fast", "1")))
+                .link(Link::new(web::get().to(default))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/")
+        .insert_header(("X-Fast", "1"))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "fast");
+
+    let req = TestRequest::with_uri("/")
+        .insert_header(("X-Fast", "0"))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "First link failed!");
+}
+
 #[actix_web::test]
 async fn test_next() {
     common::setup();
@@ -103,3 +150,1070 @@ async fn test_next() {
     assert_eq!(res.status().to_string(), "404 Not Found");
     assert_eq!(common::get_body(res).await, "Request Failed");
 }
+
+async fn final_not_found() -> impl Responder {
+    HttpResponse::NotFound()
+        .insert_header(("X-Final", "true"))
+        .body("Final Not Found")
+}
+
+#[actix_web::test]
+async fn test_next_combinators() {
+    common::setup();
+
+    let not_final =
+        IsStatus(StatusCode::NOT_FOUND).and(HasHeader(HeaderName::from_static("x-final")).not());
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(
+                    Link::new(web::get().to(final_not_found))
+                        .prefix("/final")
+                        .next(not_final),
+                )
+                .link(Link::new(web::get().to(might_fail)).next(not_final_closure))
+                .link(Link::new(web::get().to(default))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/final").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "404 Not Found");
+    assert_eq!(common::get_body(res).await, "Final Not Found");
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "First link failed!");
+}
+
+fn not_final_closure(res: &HttpResponse) -> bool {
+    res.status() == StatusCode::NOT_FOUND && !res.headers().contains_key("X-Final")
+}
+
+async fn upstream_error(_: ServiceRequest) -> Result<ServiceResponse, Error> {
+    Err(error::ErrorBadGateway("Upstream Down"))
+}
+
+async fn upstream_down(req: ServiceRequest) -> Result<ServiceResponse, Error> {
+    let (req, _) = req.into_parts();
+    Ok(ServiceResponse::from_err(
+        error::ErrorBadGateway("Upstream Down"),
+        req,
+    ))
+}
+
+#[actix_web::test]
+async fn test_on_error() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(Link::new(fn_service(upstream_error)).prefix("/error"))
+                .link(Link::new(fn_service(upstream_down)).prefix("/propagate"))
+                .link(
+                    Link::new(fn_service(upstream_down))
+                        .prefix("/next")
+                        .on_error(OnError::Next),
+                )
+                .link(
+                    Link::new(fn_service(upstream_down))
+                        .prefix("/fallback")
+                        .next(IsServerError),
+                )
+                .link(Link::new(web::get().to(default))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/error").to_request();
+    let err = test::try_call_service(&srv, req)
+        .await
+        .expect_err("missing error");
+    assert_eq!(err.to_string(), "Upstream Down");
+
+    let req = TestRequest::with_uri("/propagate").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "502 Bad Gateway");
+    assert_eq!(common::get_body(res).await, "Upstream Down");
+
+    let req = TestRequest::with_uri("/next").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "First link failed!");
+
+    let req = TestRequest::with_uri("/fallback").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "First link failed!");
+}
+
+async fn greet(name: web::Data<String>) -> String {
+    format!("Hello {}!", name.as_str())
+}
+
+#[actix_web::test]
+async fn test_on_error_app_data() {
+    common::setup();
+
+    // the fallback receives the original request along with its app data
+    let srv = test::init_service(
+        App::new()
+            .app_data(web::Data::new("world".to_owned()))
+            .service(
+                Chain::default()
+                    .link(Link::new(fn_service(upstream_down)).on_error(OnError::Next))
+                    .link(Link::new(web::get().to(greet))),
+            ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "Hello world!");
+}
+
+#[actix_web::test]
+async fn test_on_error_exclusive() {
+    common::setup();
+
+    // nested prefixes require exclusive access to the request
+    let api = Chain::new("/api").link(Link::new(fn_service(upstream_down)).prefix("/users"));
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(Link::from(api).on_error(OnError::Next))
+                .link(Link::new(web::get().to(show_path))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/api/users/1").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "none /api/users/1");
+}
+
+async fn reject_body(_body: web::Bytes) -> impl Responder {
+    HttpResponse::NotFound().finish()
+}
+
+async fn echo_body(body: web::Bytes) -> impl Responder {
+    HttpResponse::Ok().body(body)
+}
+
+#[actix_web::test]
+async fn test_body_spill() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .body_buffer_size(16)
+                .body_file_size(1024)
+                .link(Link::new(web::post().to(reject_body)))
+                .link(Link::new(web::post().to(echo_body))),
+        ),
+    )
+    .await;
+
+    let body = "0123456789".repeat(50);
+    let req = TestRequest::post()
+        .uri("/")
+        .set_payload(body.clone())
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, body);
+
+    let req = TestRequest::post()
+        .uri("/")
+        .set_payload("0123456789".repeat(200))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+async fn slow() -> impl Responder {
+    actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+    "slow"
+}
+
+async fn slow_reject() -> impl Responder {
+    actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+    HttpResponse::NotFound().finish()
+}
+
+async fn slow_echo(body: web::Bytes) -> impl Responder {
+    actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+    body
+}
+
+async fn fast() -> impl Responder {
+    "fast"
+}
+
+#[actix_web::test]
+async fn test_parallel() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .execution(Execution::Parallel)
+                .link(Link::new(web::post().to(slow_reject)))
+                .link(Link::new(web::post().to(reject_body)))
+                .link(Link::new(web::post().to(slow_echo))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::post()
+        .uri("/")
+        .set_payload("parallel body")
+        .to_request();
+    let start = Instant::now();
+    let res = test::call_service(&srv, req).await;
+    assert!(start.elapsed() < Duration::from_millis(400));
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "parallel body");
+}
+
+#[actix_web::test]
+async fn test_parallel_order() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .execution(Execution::Parallel)
+                .link(Link::new(web::get().to(slow)))
+                .link(Link::new(web::get().to(fast))),
+        ),
+    )
+    .await;
+
+    // the earlier link is served even when a later link responds first
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "slow");
+}
+
+#[actix_web::test]
+async fn test_parallel_prefix() {
+    common::setup();
+
+    let api = Chain::new("/api").link(Link::new(web::get().to(show_path)).prefix("/users/{id}"));
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .execution(Execution::Parallel)
+                .link(Link::new(fn_service(upstream_error)))
+                .link(Link::from(api)),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/api/users/7?page=2").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "7 /?page=2");
+
+    let req = TestRequest::with_uri("/about").to_request();
+    let err = test::try_call_service(&srv, req)
+        .await
+        .expect_err("missing error");
+    assert_eq!(err.to_string(), "Upstream Down");
+}
+
+#[actix_web::test]
+async fn test_parallel_app_data() {
+    common::setup();
+
+    let greetings = Rc::new(RefCell::new(vec![]));
+    let received = greetings.clone();
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .execution(Execution::Parallel)
+                .app_data(web::Data::new("world".to_owned()))
+                .link(Link::new(
+                    web::get().to(|| async { HttpResponse::NotFound().finish() }),
+                ))
+                .link(Link::new(web::get().to(greet)))
+                .mirror(Mirror::new(web::get().to(
+                    move |name: web::Data<String>| {
+                        received.borrow_mut().push(name.as_str().to_owned());
+                        async { HttpResponse::Accepted().finish() }
+                    },
+                ))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(common::get_body(res).await, "Hello world!");
+
+    actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(greetings.borrow().as_slice(), ["world"]);
+}
+
+#[actix_web::test]
+async fn test_hedged() {
+    common::setup();
+
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .execution(Execution::Hedged(Duration::from_millis(50)))
+                .link(Link::new(web::get().to(slow_reject)).prefix("/slow"))
+                .link(Link::new(web::get().to(fast)).prefix("/fast"))
+                .link(Link::new(web::get().to(move || {
+                    counter.set(counter.get() + 1);
+                    async {
+                        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+                        "hedged"
+                    }
+                }))),
+        ),
+    )
+    .await;
+
+    // the hedged link starts before the slow link forwards the request
+    let req = TestRequest::with_uri("/slow").to_request();
+    let start = Instant::now();
+    let res = test::call_service(&srv, req).await;
+    assert!(start.elapsed() < Duration::from_millis(400));
+    assert_eq!(common::get_body(res).await, "hedged");
+    assert_eq!(calls.get(), 1);
+
+    let req = TestRequest::with_uri("/fast").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "fast");
+    assert_eq!(calls.get(), 1);
+}
+
+#[actix_web::test]
+async fn test_selection() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .selection(Selection::RoundRobin)
+                .link(Link::new(web::get().to(|| async { "first" })))
+                .link(Link::new(web::get().to(|| async { "second" })).weight(2))
+                .link(Link::new(
+                    web::get().to(|| async { HttpResponse::NotFound().finish() }),
+                )),
+        ),
+    )
+    .await;
+
+    let mut bodies = vec![];
+    for _ in 0..4 {
+        let req = TestRequest::with_uri("/").to_request();
+        let res = test::call_service(&srv, req).await;
+        bodies.push(common::get_body(res).await);
+    }
+    assert_eq!(bodies, ["first", "second", "second", "first"]);
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .selection(Selection::StickyCookie("session".to_owned()))
+                .link(Link::new(web::get().to(|| async { "first" })))
+                .link(Link::new(web::get().to(|| async { "second" }))),
+        ),
+    )
+    .await;
+
+    let mut bodies = vec![];
+    for _ in 0..4 {
+        let req = TestRequest::with_uri("/")
+            .insert_header(("Cookie", "a=b; session=1234"))
+            .to_request();
+        let res = test::call_service(&srv, req).await;
+        bodies.push(common::get_body(res).await);
+    }
+    assert!(bodies.iter().all(|body| *body == bodies[0]));
+}
+
+#[actix_web::test]
+async fn test_circuit_breaker() {
+    common::setup();
+
+    let healthy = Rc::new(Cell::new(false));
+    let calls = Rc::new(Cell::new(0));
+    let (state, counter) = (healthy.clone(), calls.clone());
+    let breaker =
+        CircuitBreaker::new(2, Duration::from_secs(10)).open_duration(Duration::from_millis(100));
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(
+                    Link::new(web::get().to(move || {
+                        counter.set(counter.get() + 1);
+                        let res = match state.get() {
+                            true => HttpResponse::Ok().body("upstream"),
+                            false => HttpResponse::ServiceUnavailable().finish(),
+                        };
+                        async move { res }
+                    }))
+                    .next(IsServerError)
+                    .circuit_breaker(breaker.clone()),
+                )
+                .link(Link::new(web::get().to(default))),
+        ),
+    )
+    .await;
+
+    let call = async || {
+        let req = TestRequest::with_uri("/").to_request();
+        common::get_body(test::call_service(&srv, req).await).await
+    };
+
+    assert_eq!(call().await, "First link failed!");
+    assert_eq!(call().await, "First link failed!");
+    assert_eq!(breaker.state(), CircuitState::Open);
+    assert_eq!(call().await, "First link failed!");
+    assert_eq!(calls.get(), 2);
+
+    // failed probe re-opens the circuit
+    actix_web::rt::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    assert_eq!(call().await, "First link failed!");
+    assert_eq!(breaker.state(), CircuitState::Open);
+    assert_eq!(calls.get(), 3);
+
+    // successful probe closes the circuit
+    healthy.set(true);
+    actix_web::rt::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(call().await, "upstream");
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    assert_eq!(calls.get(), 4);
+}
+
+#[actix_web::test]
+async fn test_mirror() {
+    common::setup();
+
+    let bodies = Rc::new(RefCell::new(vec![]));
+    let received = bodies.clone();
+    let stats = Arc::new(MirrorStats::default());
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(Link::new(web::post().to(echo_body)))
+                .mirror(
+                    Mirror::new(web::post().to(move |body: web::Bytes| {
+                        received.borrow_mut().push(body);
+                        async { HttpResponse::Accepted().finish() }
+                    }))
+                    .compare(true)
+                    .stats(stats.clone()),
+                ),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::post()
+        .uri("/")
+        .set_payload("mirrored body")
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "mirrored body");
+
+    actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(bodies.borrow().as_slice(), ["mirrored body"]);
+    assert_eq!(stats.sent(), 1);
+    assert_eq!(stats.mismatches(), 1);
+    assert_eq!(stats.in_flight(), 0);
+}
+
+#[actix_web::test]
+async fn test_mirror_match_info() {
+    common::setup();
+
+    let ids = Rc::new(RefCell::new(vec![]));
+    let received = ids.clone();
+    let srv = test::init_service(
+        App::new().service(
+            Chain::new("/users/{id}")
+                .link(Link::new(web::get().to(default)))
+                .mirror(Mirror::new(web::get().to(move |req: HttpRequest| {
+                    let id = req.match_info().get("id").map(str::to_owned);
+                    received.borrow_mut().push(id);
+                    async { HttpResponse::Accepted().finish() }
+                }))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/users/42").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(ids.borrow().as_slice(), [Some("42".to_owned())]);
+}
+
+async fn show_path(req: HttpRequest) -> impl Responder {
+    let id = req.match_info().get("id").unwrap_or("none").to_owned();
+    let res = HttpResponse::Ok().body(format!("{id} {}", req.uri()));
+    match req.headers().contains_key("X-Reject") {
+        true => HttpResponse::NotFound().finish(),
+        false => res,
+    }
+}
+
+#[actix_web::test]
+async fn test_pattern() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(Link::new(web::get().to(show_path)).prefix("/api"))
+                .link(Link::new(web::get().to(show_path)).prefix("/users/{id}"))
+                .link(Link::new(web::get().to(show_path)).prefix("/café"))
+                .link(Link::new(web::get().to(default)).prefix("/apiary"))
+                .link(Link::new(web::get().to(|req: HttpRequest| async move {
+                    format!("fallback {:?}", req.match_info().get("id"))
+                }))),
+        ),
+    )
+    .await;
+
+    let call = async |uri: &str, reject: bool| {
+        let mut req = TestRequest::with_uri(uri);
+        if reject {
+            req = req.insert_header(("X-Reject", "1"));
+        }
+        common::get_body(test::call_service(&srv, req.to_request()).await).await
+    };
+
+    assert_eq!(call("/api", false).await, "none /");
+    assert_eq!(call("/api/v1?a=b", false).await, "none /v1?a=b");
+    assert_eq!(call("/apiary", false).await, "First link failed!");
+    assert_eq!(
+        call("/users/42/profile?x=1", false).await,
+        "42 /profile?x=1"
+    );
+    assert_eq!(call("/users/42/profile", true).await, "fallback None");
+    assert_eq!(call("/caf%C3%A9/a%20b", false).await, "none /a%20b");
+}
+
+#[actix_web::test]
+async fn test_default_service() {
+    common::setup();
+
+    let chain = || {
+        Chain::default()
+            .link(Link::new(web::get().to(might_fail)).prefix("/first"))
+            .link(Link::new(web::get().to(might_fail)).prefix("/last"))
+            .default_service(web::to(|| async { HttpResponse::Gone().body("default") }))
+    };
+    let srv = test::init_service(App::new().service(chain())).await;
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::GONE);
+    assert_eq!(common::get_body(res).await, "default");
+
+    let req = TestRequest::with_uri("/last").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(common::get_body(res).await, "Request Failed");
+
+    let srv =
+        test::init_service(App::new().service(chain().on_exhausted(Exhausted::Default))).await;
+    let req = TestRequest::with_uri("/last").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::GONE);
+    assert_eq!(common::get_body(res).await, "default");
+}
+
+#[actix_web::test]
+async fn test_trace() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .debug_header(HeaderName::from_static("x-chain-link"))
+                .link(Link::new(web::get().to(might_fail)).name("unstable"))
+                .link(Link::new(web::get().to(default))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    let header = res.headers().get("x-chain-link").expect("missing header");
+    let header = header.to_str().expect("invalid header").to_owned();
+    assert!(
+        header.starts_with("served=1 tried=unstable:404:"),
+        "{header}"
+    );
+
+    let ext = res.response().extensions();
+    let trace = ext.get::<ChainTrace>().expect("missing trace");
+    assert_eq!(trace.attempts.len(), 2);
+    assert_eq!(trace.attempts[0].name.as_deref(), Some("unstable"));
+    assert_eq!(trace.attempts[0].status, Some(StatusCode::NOT_FOUND));
+    let served = trace.served().expect("missing served link");
+    assert_eq!(served.index, 1);
+    assert_eq!(served.status, Some(StatusCode::OK));
+}
+
+#[actix_web::test]
+async fn test_trace_nested() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .debug_header(HeaderName::from_static("x-chain-link"))
+                .link(Link::new(web::get().to(might_fail)).name("unstable"))
+                .link(Link::new(web::get().to(default)).name("backup"))
+                .wrap(actix_web::middleware::DefaultHeaders::new()),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    let header = res.headers().get("x-chain-link").expect("missing header");
+    let header = header.to_str().expect("invalid header").to_owned();
+    assert!(
+        header.starts_with("served=backup tried=unstable:404:"),
+        "{header}"
+    );
+
+    let ext = res.response().extensions();
+    let trace = ext.get::<ChainTrace>().expect("missing trace");
+    assert_eq!(trace.route(), ["0", "backup"]);
+    assert!(
+        trace
+            .to_string()
+            .starts_with("served=0>backup tried=0:200:")
+    );
+    let nested = trace.attempts[0].nested.as_ref().expect("missing nested");
+    assert_eq!(nested.attempts.len(), 2);
+    assert_eq!(nested.attempts[0].status, Some(StatusCode::NOT_FOUND));
+}
+
+async fn slow_not_found() -> impl Responder {
+    actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+    HttpResponse::NotFound().finish()
+}
+
+#[actix_web::test]
+async fn test_handle() {
+    common::setup();
+
+    let handle = ChainHandle::default();
+    handle.push("a", || Link::new(web::get().to(slow_not_found)));
+    handle.push("b", || Link::new(web::get().to(|| async { "b" })));
+
+    let srv = Rc::new(
+        test::init_service(
+            App::new().service(
+                Chain::default()
+                    .handle(handle.clone())
+                    .link(Link::new(web::get().to(default))),
+            ),
+        )
+        .await,
+    );
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "b");
+
+    // in-flight requests keep the links they started with
+    let inflight = actix_web::rt::spawn({
+        let srv = Rc::clone(&srv);
+        async move {
+            let req = TestRequest::with_uri("/").to_request();
+            let res = test::call_service(&srv, req).await;
+            common::get_body(res).await
+        }
+    });
+    actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    handle.update(|links| {
+        links.remove("b");
+        links.push("c", || Link::new(web::get().to(|| async { "c" })));
+    });
+    assert_eq!(
+        handle.ids(),
+        vec![("a".to_owned(), true), ("c".to_owned(), true)]
+    );
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "c");
+    assert_eq!(inflight.await.expect("request panicked"), "b");
+
+    // disabled links are skipped, and static links remain as a fallback
+    handle.set_enabled("c", false);
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "First link failed!");
+
+    handle.move_to("c", 0);
+    handle.set_enabled("c", true);
+    let start = Instant::now();
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "c");
+    assert!(start.elapsed() < Duration::from_millis(200));
+}
+
+#[actix_web::test]
+async fn test_handle_retry() {
+    common::setup();
+
+    let handle = ChainHandle::default();
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .handle(handle.clone())
+                .link(Link::new(web::get().to(default))),
+        ),
+    )
+    .await;
+
+    let fail = Arc::new(AtomicBool::new(true));
+    let failing = fail.clone();
+    handle.push("a", move || {
+        let fail = failing.clone();
+        Link::new(fn_factory(move || {
+            let fail = fail.load(Ordering::Relaxed);
+            async move {
+                match fail {
+                    true => Err(()),
+                    false => Ok(fn_service(|req: ServiceRequest| async {
+                        Ok(req.into_response(HttpResponse::Ok().body("a")))
+                    })),
+                }
+            }
+        }))
+    });
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "First link failed!");
+
+    // failed updates are retried without another change to the handle
+    fail.store(false, Ordering::Relaxed);
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "a");
+}
+
+#[actix_web::test]
+async fn test_explain() {
+    common::setup();
+
+    let called = Rc::new(Cell::new(false));
+    let service = {
+        let called = Rc::clone(&called);
+        fn_service(move |req: ServiceRequest| {
+            called.set(true);
+            async move { Ok(req.into_response(HttpResponse::Ok().finish())) }
+        })
+    };
+
+    let users = Chain::new("/users/{id}")
+        .link(Link::new(service.clone()).prefix("/posts").name("posts"))
+        .link(Link::new(web::get().to(show_path)).name("profile"));
+    let chain = Chain::default()
+        .link(
+            Link::new(web::get().to(default))
+                .guard(guard::Header("Host", "admin.example.com"))
+                .name("admin"),
+        )
+        .link(Link::from(users).name("users"))
+        .link(Link::new(service).name("fallback"));
+
+    let req = TestRequest::with_uri("/users/42/posts/7?page=2").to_srv_request();
+    let explain = chain.explain(&req);
+    assert!(!called.get());
+    assert_eq!(explain.route(), vec!["users", "posts"]);
+
+    let admin = &explain.links[0];
+    assert!(admin.prefix_matched);
+    assert!(!admin.guards_passed);
+
+    let users = &explain.links[1];
+    assert!(users.matched());
+    assert_eq!(users.params, vec![("id".to_owned(), "42".to_owned())]);
+    assert_eq!(
+        users.uri.as_ref().map(|u| u.to_string()).as_deref(),
+        Some("/posts/7?page=2")
+    );
+
+    let nested = users.nested.as_ref().expect("missing nested chain");
+    assert_eq!(nested.links.len(), 2);
+    assert_eq!(
+        nested.links[0]
+            .uri
+            .as_ref()
+            .map(|u| u.to_string())
+            .as_deref(),
+        Some("/7?page=2")
+    );
+    assert!(explain.to_string().contains("\n  posts prefix=\"/posts\""));
+
+    assert_route(
+        &chain,
+        TestRequest::with_uri("/users/42"),
+        &["users", "profile"],
+    );
+    assert_route(
+        &chain,
+        TestRequest::with_uri("/").insert_header(("Host", "admin.example.com")),
+        &["admin"],
+    );
+    assert_route(&chain, TestRequest::with_uri("/about"), &["fallback"]);
+    assert!(!called.get());
+}
+
+#[actix_web::test]
+async fn test_explain_parallel() {
+    common::setup();
+
+    let chain = Chain::default()
+        .execution(Execution::Parallel)
+        .link(
+            Link::new(web::get().to(show_path))
+                .prefix("/users/{id}")
+                .name("users"),
+        )
+        .link(Link::new(web::get().to(default)).name("fallback"));
+
+    // concurrent links receive their own prefix-stripped requests
+    let req = TestRequest::with_uri("/users/42/posts?page=2").to_srv_request();
+    let explain = chain.explain(&req);
+    assert_eq!(explain.route(), vec!["users"]);
+
+    let users = &explain.links[0];
+    assert_eq!(users.params, vec![("id".to_owned(), "42".to_owned())]);
+    assert_eq!(
+        users.uri.as_ref().map(|u| u.to_string()).as_deref(),
+        Some("/posts?page=2")
+    );
+}
+
+#[actix_web::test]
+async fn test_retry() {
+    common::setup();
+
+    let attempts = Rc::new(Cell::new(0));
+    let flaky = {
+        let attempts = Rc::clone(&attempts);
+        move |body: web::Bytes| {
+            attempts.set(attempts.get() + 1);
+            let res = match attempts.get() < 3 {
+                true => HttpResponse::ServiceUnavailable().finish(),
+                false => HttpResponse::Ok().body(body),
+            };
+            async move { res }
+        }
+    };
+    let retry = Retry::new(3)
+        .backoff(Duration::from_millis(20))
+        .jitter(false);
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(
+                    Link::new(web::route().to(flaky))
+                        .retry(retry.clone())
+                        .next(IsServerError),
+                )
+                .link(Link::new(web::route().to(fast))),
+        ),
+    )
+    .await;
+
+    // each attempt replays the buffered body
+    let start = Instant::now();
+    let req = TestRequest::put()
+        .uri("/")
+        .set_payload("retry body")
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(attempts.get(), 3);
+    assert!(start.elapsed() >= Duration::from_millis(60));
+    assert_eq!(common::get_body(res).await, "retry body");
+
+    // non-idempotent methods are not retried by default
+    attempts.set(0);
+    let req = TestRequest::post().uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(attempts.get(), 1);
+    assert_eq!(common::get_body(res).await, "fast");
+
+    // attempts are limited and the final response is evaluated by next
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(
+                    Link::new(web::get().to(|| async { HttpResponse::BadGateway().finish() }))
+                        .retry(retry.retry_on(IsStatus(StatusCode::BAD_GATEWAY)))
+                        .next(IsServerError),
+                )
+                .link(Link::new(web::get().to(fast))),
+        ),
+    )
+    .await;
+    let req = TestRequest::get().uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "fast");
+}
+
+async fn json_body(req: HttpRequest) -> impl Responder {
+    let body = match req.path() {
+        "/missing" => r#"{"error":"not_found"}"#,
+        _ => r#"{"data":{"id":1}}"#,
+    };
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(body)
+}
+
+async fn stream_body() -> impl Responder {
+    let chunks = ["large ", "streamed ", "body"].map(|c| Ok::<_, Error>(web::Bytes::from(c)));
+    HttpResponse::Ok().streaming(StreamBody(chunks.into_iter()))
+}
+
+struct StreamBody<I>(I);
+
+impl<I: Iterator + Unpin> futures_core::Stream for StreamBody<I> {
+    type Item = I::Item;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        std::task::Poll::Ready(self.get_mut().0.next())
+    }
+}
+
+#[actix_web::test]
+async fn test_next_body() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(
+                    Link::new(web::get().to(json_body))
+                        .prefix("/json")
+                        .next_body(JsonPointer::new("/error", "not_found")),
+                )
+                .link(
+                    Link::new(web::get().to(stream_body))
+                        .prefix("/stream")
+                        .next_body_limit(8)
+                        .next_body(BodyMatches(Regex::new("body").unwrap())),
+                )
+                .link(Link::new(web::get().to(default))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/json/missing").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "First link failed!");
+
+    let req = TestRequest::with_uri("/json/found").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, r#"{"data":{"id":1}}"#);
+
+    // bodies beyond the limit never match and are returned intact
+    let req = TestRequest::with_uri("/stream").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "large streamed body");
+}
+
+async fn upstream_missing() -> impl Responder {
+    HttpResponse::NotFound()
+        .insert_header(("Set-Cookie", "session=abc"))
+        .body("no such user")
+}
+
+async fn render_previous(previous: Option<web::ReqData<PreviousResponse>>) -> impl Responder {
+    let Some(previous) = previous else {
+        return HttpResponse::Ok().body("no previous response");
+    };
+    let body = previous.body.clone().unwrap_or_default();
+    let mut res = HttpResponse::Ok();
+    if let Some(cookie) = previous.headers.get("set-cookie") {
+        res.insert_header(("Set-Cookie", cookie.clone()));
+    }
+    res.body(format!(
+        "{} returned {} for {:?}",
+        previous.name.as_deref().unwrap_or("?"),
+        previous.status.as_u16(),
+        std::str::from_utf8(&body).unwrap_or_default()
+    ))
+}
+
+#[actix_web::test]
+async fn test_previous_response() {
+    common::setup();
+
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .link(Link::new(web::get().to(upstream_missing)).name("users"))
+                .link(Link::new(web::get().to(render_previous))),
+        ),
+    )
+    .await;
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(
+        res.headers()
+            .get("set-cookie")
+            .and_then(|v| v.to_str().ok()),
+        Some("session=abc")
+    );
+    assert_eq!(
+        common::get_body(res).await,
+        r#"users returned 404 for "no such user""#
+    );
+
+    // the default service also receives the discarded response
+    let srv = test::init_service(
+        App::new().service(
+            Chain::default()
+                .on_exhausted(Exhausted::Default)
+                .default_service(web::get().to(render_previous))
+                .link(Link::new(web::get().to(upstream_missing)).name("users")),
+        ),
+    )
+    .await;
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert!(
+        common::get_body(res)
+            .await
+            .starts_with("users returned 404")
+    );
+}
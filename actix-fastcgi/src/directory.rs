@@ -0,0 +1,64 @@
+//! Directory Listing Utilities
+
+use std::{fmt::Write, io, path::Path};
+
+use actix_files::Directory;
+use actix_web::{HttpRequest, HttpResponse, dev::ServiceResponse};
+use percent_encoding::{CONTROLS, utf8_percent_encode};
+use v_htmlescape::escape as escape_html_entity;
+
+/// Directory listing renderer used when no index file is found
+pub(crate) type DirectoryRenderer =
+    dyn Fn(&Directory, &HttpRequest) -> Result<ServiceResponse, io::Error>;
+
+/// Default directory listing renderer.
+///
+/// Mirrors the listing rendered by [`actix_files::Files::show_files_listing`].
+pub(crate) fn directory_listing(
+    dir: &Directory,
+    req: &HttpRequest,
+) -> Result<ServiceResponse, io::Error> {
+    let index_of = format!("Index of {}", req.path());
+    let mut body = String::new();
+    let base = Path::new(req.path());
+
+    for entry in dir.path.read_dir()? {
+        if !dir.is_visible(&entry) {
+            continue;
+        }
+        let entry = entry?;
+        let path = match entry.path().strip_prefix(&dir.path) {
+            Ok(p) if cfg!(windows) => base.join(p).to_string_lossy().replace('\\', "/"),
+            Ok(p) => base.join(p).to_string_lossy().into_owned(),
+            Err(_) => continue,
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        // if file is a directory, add '/' to the end of the name
+        let name = entry.file_name();
+        let _ = write!(
+            body,
+            "<li><a href=\"{}\">{}{}</a></li>",
+            utf8_percent_encode(&path, CONTROLS),
+            escape_html_entity(&name.to_string_lossy()),
+            if metadata.is_dir() { "/" } else { "" },
+        );
+    }
+
+    let html = format!(
+        "<html>\
+         <head><title>{index_of}</title></head>\
+         <body><h1>{index_of}</h1>\
+         <ul>\
+         {body}\
+         </ul></body>\n</html>",
+    );
+    Ok(ServiceResponse::new(
+        req.clone(),
+        HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(html),
+    ))
+}
@@ -29,6 +29,16 @@ pub enum Error {
     /// FastCGI Status header code is invalid
     #[display("Invalid status code passed")]
     StatusCode(http::status::InvalidStatusCode),
+
+    /// Requested path is a directory without an index file
+    #[display("Unable to render directory without index file")]
+    #[from(ignore)]
+    IsDirectory,
+
+    /// Connection pool failed to provide a connection
+    #[display("FastCGI connection pool unavailable")]
+    #[from(ignore)]
+    PoolUnavailable,
 }
 
 impl From<std::convert::Infallible> for Error {
@@ -38,8 +48,12 @@ impl From<std::convert::Infallible> for Error {
 }
 
 impl ResponseError for Error {
-    /// Returns `500 Internal Server Error`.
+    /// Returns `403 Forbidden` for directories and
+    /// `500 Internal Server Error` otherwise.
     fn status_code(&self) -> actix_web::http::StatusCode {
-        actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            Self::IsDirectory => actix_web::http::StatusCode::FORBIDDEN,
+            _ => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 }
@@ -1,17 +1,25 @@
 //! FastCGI Service Factory
 
-use std::{path::PathBuf, rc::Rc};
+use std::{
+    io,
+    path::PathBuf,
+    rc::Rc,
+    sync::{Arc, atomic::AtomicUsize},
+};
 
+use actix_files::Directory;
 use actix_service::ServiceFactory;
 use actix_web::{
-    Error,
+    Error, HttpRequest,
     dev::{AppService, HttpServiceFactory, ResourceDef, ServiceRequest, ServiceResponse},
     guard::Guard,
 };
 use futures_core::future::LocalBoxFuture;
 
 use crate::{
-    SockPool, pool,
+    Buffering, Metrics, SockPool,
+    directory::{DirectoryRenderer, directory_listing},
+    pool,
     stream::{DEFAULT_ADDRESS, StreamAddr},
 };
 
@@ -36,7 +44,14 @@ pub struct FastCGI {
     guards: Vec<Rc<dyn Guard>>,
     root: PathBuf,
     indexes: Vec<String>,
+    show_index: bool,
+    redirect_to_slash: bool,
+    renderer: Rc<DirectoryRenderer>,
+    buffering: Buffering,
     fastcgi_pool: SockPool,
+    upstream: Rc<str>,
+    metrics: Option<Arc<dyn Metrics>>,
+    active: Arc<AtomicUsize>,
 }
 
 impl FastCGI {
@@ -61,6 +76,7 @@ impl FastCGI {
                 PathBuf::new()
             }
         };
+        let upstream = Rc::from(fastcgi_address);
         let fastcgi_address = match StreamAddr::try_from(fastcgi_address) {
             Ok(addr) => addr,
             Err(_) => {
@@ -74,7 +90,14 @@ impl FastCGI {
             guards: Vec::new(),
             root,
             indexes: Vec::new(),
+            show_index: false,
+            redirect_to_slash: true,
+            renderer: Rc::new(directory_listing),
+            buffering: Buffering::default(),
             fastcgi_pool: SockPool::builder(mgr).build().unwrap(),
+            upstream,
+            metrics: None,
+            active: Arc::default(),
         }
     }
 
@@ -112,6 +135,84 @@ impl FastCGI {
         self.indexes.push(index.into());
         self
     }
+
+    /// Show files listing for directories.
+    ///
+    /// By default show files listing is disabled and directories
+    /// without an index file respond with `403 Forbidden`.
+    ///
+    /// When used with [`FastCGI::index_file()`], files listing is shown as a fallback
+    /// when the index file is not found.
+    pub fn show_files_listing(mut self) -> Self {
+        self.show_index = true;
+        self
+    }
+
+    /// Set custom directory renderer.
+    ///
+    /// Matches the signature of [`actix_files::Files::files_listing_renderer`].
+    pub fn files_listing_renderer<F>(mut self, f: F) -> Self
+    where
+        for<'r, 's> F:
+            Fn(&'r Directory, &'s HttpRequest) -> Result<ServiceResponse, io::Error> + 'static,
+    {
+        self.renderer = Rc::new(f);
+        self
+    }
+
+    /// Configure redirects to a slash-ended path when browsing a directory.
+    ///
+    /// Mirrors Apache's `DirectorySlash` directive by responding with a
+    /// `301 Moved Permanently` to the same path with a trailing slash.
+    ///
+    /// By default redirects are enabled.
+    pub fn redirect_to_slash_directory(mut self, redirect: bool) -> Self {
+        self.redirect_to_slash = redirect;
+        self
+    }
+
+    /// Configure the response body [`Buffering`] mode.
+    ///
+    /// Default is [`Buffering::Streaming`], which forwards every stdout
+    /// record as soon as it is received. Backends may switch a single
+    /// response to streaming with an `X-Accel-Buffering: no` header.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::App;
+    /// use actix_fastcgi::{Buffering, FastCGI};
+    ///
+    /// App::new().service(
+    ///     FastCGI::new("/", "/my/php/files", "tcp://127.0.0.1:9000")
+    ///         .buffering(Buffering::Buffered(64 * 1024))
+    /// );
+    /// ```
+    pub fn buffering(mut self, buffering: Buffering) -> Self {
+        self.buffering = buffering;
+        self
+    }
+
+    /// Record request, latency and pool metrics into the given collector.
+    ///
+    /// Metrics are labeled with the configured FastCGI address, so a single
+    /// collector may be shared between multiple services and workers.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::sync::Arc;
+    /// use actix_web::App;
+    /// use actix_fastcgi::{FastCGI, MemoryMetrics};
+    ///
+    /// let metrics = Arc::new(MemoryMetrics::default());
+    /// App::new().service(
+    ///     FastCGI::new("/", "/my/php/files", "tcp://127.0.0.1:9000")
+    ///         .metrics(metrics.clone())
+    /// );
+    /// ```
+    pub fn metrics<M: Metrics + 'static>(mut self, metrics: Arc<M>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
 }
 
 impl HttpServiceFactory for FastCGI {
@@ -150,7 +251,14 @@ impl ServiceFactory<ServiceRequest> for FastCGI {
         let inner = FastCGIInner {
             root: self.root.clone(),
             indexes: self.indexes.clone(),
+            show_index: self.show_index,
+            redirect_to_slash: self.redirect_to_slash,
+            renderer: self.renderer.clone(),
+            buffering: self.buffering,
             fastcgi_pool: self.fastcgi_pool.clone(),
+            upstream: self.upstream.clone(),
+            metrics: self.metrics.clone(),
+            active: self.active.clone(),
         };
         Box::pin(async move { Ok(FastCGIService(Rc::new(inner))) })
     }
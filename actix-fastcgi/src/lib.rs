@@ -1,5 +1,7 @@
+mod directory;
 mod error;
 mod factory;
+pub mod metrics;
 mod payload;
 mod pool;
 mod service;
@@ -7,7 +9,8 @@ mod stream;
 
 pub use error::Error;
 pub use factory::FastCGI;
-pub use payload::{RequestStream, ResponseStream};
+pub use metrics::{MemoryMetrics, Metrics};
+pub use payload::{Buffering, RequestStream, ResponseStream};
 pub use pool::SockPool;
 pub use service::FastCGIService;
 pub use stream::{SockStream, StreamAddr};
@@ -0,0 +1,491 @@
+//! FastCGI Upstream Metrics
+
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    rc::Rc,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use actix_web::http::StatusCode;
+
+use crate::SockPool;
+
+/// Histogram bucket boundaries (in seconds) used by [`MemoryMetrics`]
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Snapshot of the FastCGI connection pool state.
+///
+/// Connections are detached from the pool while serving a request, so
+/// `size` counts both idle pooled connections and connections in use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStatus {
+    /// Maximum number of connections allowed in the pool
+    pub max_size: usize,
+    /// Number of open connections, either idle or in use
+    pub size: usize,
+    /// Number of idle connections available for reuse
+    pub idle: usize,
+    /// Number of requests waiting for a connection
+    pub waiting: usize,
+    /// Number of connections actively serving a request
+    pub in_use: usize,
+}
+
+/// Summary of a single completed FastCGI request.
+#[derive(Clone, Debug, Default)]
+pub struct RequestRecord {
+    /// Response status, or `None` if the request failed before a response
+    pub status: Option<StatusCode>,
+    /// Time until the first stdout record was received from the backend
+    pub ttfb: Option<Duration>,
+    /// Time until the response body was completed or dropped
+    pub duration: Duration,
+    /// Number of request body bytes sent to the backend
+    pub bytes_in: u64,
+    /// Number of response bytes received from the backend
+    pub bytes_out: u64,
+}
+
+/// Metrics collector for [`FastCGI`](crate::FastCGI) services.
+///
+/// Every method receives the configured upstream address so that a single
+/// collector may be shared between multiple services and actix workers.
+pub trait Metrics: Send + Sync {
+    /// Called once a request has completed, failed or been dropped.
+    fn record_request(&self, upstream: &str, record: &RequestRecord);
+
+    /// Called whenever a connection to the backend could not be established.
+    fn record_connect_failure(&self, upstream: &str);
+
+    /// Called for every stderr record emitted by the backend.
+    fn record_stderr(&self, upstream: &str, bytes: usize);
+
+    /// Called with the latest connection pool state of the calling worker.
+    ///
+    /// Each actix worker has its own pool for every upstream, so the
+    /// state only covers the worker thread the method is called from.
+    fn record_pool(&self, upstream: &str, status: PoolStatus);
+}
+
+/// Simple cumulative histogram of observed durations.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    /// Cumulative counts for each of the [`DEFAULT_BUCKETS`]
+    pub buckets: [u64; DEFAULT_BUCKETS.len()],
+    /// Sum of all observations in seconds
+    pub sum: f64,
+    /// Total number of observations
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Record a new observation.
+    pub fn observe(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        DEFAULT_BUCKETS
+            .iter()
+            .zip(self.buckets.iter_mut())
+            .filter(|(bound, _)| secs <= **bound)
+            .for_each(|(_, count)| *count += 1);
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+/// Prometheus metric family name, help text and value getter
+type Family<T> = (&'static str, &'static str, fn(&UpstreamMetrics) -> T);
+
+/// Collected metrics for a single upstream.
+#[derive(Clone, Debug, Default)]
+pub struct UpstreamMetrics {
+    /// Request counts by status class (`2xx`, `5xx`, ..., `error`)
+    pub requests: BTreeMap<&'static str, u64>,
+    /// Time-to-first-byte histogram
+    pub ttfb: Histogram,
+    /// Total request duration histogram
+    pub duration: Histogram,
+    /// Request body bytes sent to the backend
+    pub bytes_in: u64,
+    /// Response bytes received from the backend
+    pub bytes_out: u64,
+    /// Latest connection pool state, summed across every worker
+    pub pool: PoolStatus,
+    /// Number of failed connection attempts
+    pub connect_failures: u64,
+    /// Number of stderr bytes emitted by the backend
+    pub stderr_bytes: u64,
+}
+
+/// Default in-memory [`Metrics`] implementation.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use actix_web::App;
+/// use actix_fastcgi::{FastCGI, metrics::MemoryMetrics};
+///
+/// let metrics = Arc::new(MemoryMetrics::default());
+/// let app = App::new()
+///     .service(FastCGI::new("/", ".", "tcp://127.0.0.1:9000").metrics(metrics.clone()));
+///
+/// println!("{}", metrics.render_prometheus());
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryMetrics(Mutex<Collected>);
+
+/// Metrics collected by [`MemoryMetrics`]
+#[derive(Debug, Default)]
+struct Collected {
+    upstreams: BTreeMap<String, UpstreamMetrics>,
+    /// Latest pool state reported by each worker for every upstream
+    pools: BTreeMap<String, HashMap<ThreadId, PoolStatus>>,
+}
+
+impl MemoryMetrics {
+    /// Lock the collected metrics, ignoring poisoning by a panicked writer
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, Collected> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[inline]
+    fn update<F: FnOnce(&mut UpstreamMetrics)>(&self, upstream: &str, f: F) {
+        let mut collected = self.lock();
+        let map = &mut collected.upstreams;
+        match map.get_mut(upstream) {
+            Some(metrics) => f(metrics),
+            None => f(map.entry(upstream.to_owned()).or_default()),
+        }
+    }
+
+    /// Retrieve a copy of the metrics collected for every upstream.
+    ///
+    /// The pool state of each upstream is summed across every worker.
+    pub fn snapshot(&self) -> BTreeMap<String, UpstreamMetrics> {
+        let collected = self.lock();
+        let mut snapshot = collected.upstreams.clone();
+        for (upstream, workers) in collected.pools.iter() {
+            let pool = &mut snapshot.entry(upstream.clone()).or_default().pool;
+            for status in workers.values() {
+                pool.max_size += status.max_size;
+                pool.size += status.size;
+                pool.idle += status.idle;
+                pool.waiting += status.waiting;
+                pool.in_use += status.in_use;
+            }
+        }
+        snapshot
+    }
+
+    /// Render collected metrics in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+
+        let name = "fastcgi_requests_total";
+        header(
+            &mut out,
+            name,
+            "Total FastCGI requests by status class.",
+            "counter",
+        );
+        for (upstream, m) in snapshot.iter() {
+            let upstream = escape(upstream);
+            for (class, count) in m.requests.iter() {
+                let _ = writeln!(
+                    out,
+                    "{name}{{upstream=\"{upstream}\",class=\"{class}\"}} {count}"
+                );
+            }
+        }
+
+        let histograms: [Family<Histogram>; 2] = [
+            (
+                "fastcgi_ttfb_seconds",
+                "Time to first byte from the FastCGI backend.",
+                |m| m.ttfb.clone(),
+            ),
+            (
+                "fastcgi_request_duration_seconds",
+                "Total FastCGI request duration.",
+                |m| m.duration.clone(),
+            ),
+        ];
+        for (name, help, get) in histograms {
+            header(&mut out, name, help, "histogram");
+            for (upstream, m) in snapshot.iter() {
+                let upstream = escape(upstream);
+                let hist = get(m);
+                for (le, count) in DEFAULT_BUCKETS.iter().zip(hist.buckets.iter()) {
+                    let _ = writeln!(
+                        out,
+                        "{name}_bucket{{upstream=\"{upstream}\",le=\"{le}\"}} {count}"
+                    );
+                }
+                let count = hist.count;
+                let _ = writeln!(
+                    out,
+                    "{name}_bucket{{upstream=\"{upstream}\",le=\"+Inf\"}} {count}"
+                );
+                let _ = writeln!(out, "{name}_sum{{upstream=\"{upstream}\"}} {}", hist.sum);
+                let _ = writeln!(out, "{name}_count{{upstream=\"{upstream}\"}} {count}");
+            }
+        }
+
+        let counters: [Family<u64>; 4] = [
+            (
+                "fastcgi_request_bytes_total",
+                "Request body bytes sent to the FastCGI backend.",
+                |m| m.bytes_in,
+            ),
+            (
+                "fastcgi_response_bytes_total",
+                "Response bytes received from the FastCGI backend.",
+                |m| m.bytes_out,
+            ),
+            (
+                "fastcgi_connect_failures_total",
+                "Failed FastCGI connection attempts.",
+                |m| m.connect_failures,
+            ),
+            (
+                "fastcgi_stderr_bytes_total",
+                "Stderr bytes emitted by the FastCGI backend.",
+                |m| m.stderr_bytes,
+            ),
+        ];
+        for (name, help, get) in counters {
+            header(&mut out, name, help, "counter");
+            for (upstream, m) in snapshot.iter() {
+                let _ = writeln!(
+                    out,
+                    "{name}{{upstream=\"{}\"}} {}",
+                    escape(upstream),
+                    get(m)
+                );
+            }
+        }
+
+        let gauges: [Family<usize>; 5] = [
+            (
+                "fastcgi_pool_max_size",
+                "Maximum FastCGI connection pool size across all workers.",
+                |m| m.pool.max_size,
+            ),
+            (
+                "fastcgi_pool_size",
+                "Current FastCGI connection pool size across all workers.",
+                |m| m.pool.size,
+            ),
+            ("fastcgi_pool_idle", "Idle FastCGI connections.", |m| {
+                m.pool.idle
+            }),
+            (
+                "fastcgi_pool_waiting",
+                "Requests waiting for a FastCGI connection.",
+                |m| m.pool.waiting,
+            ),
+            (
+                "fastcgi_pool_in_use",
+                "FastCGI connections serving a request.",
+                |m| m.pool.in_use,
+            ),
+        ];
+        for (name, help, get) in gauges {
+            header(&mut out, name, help, "gauge");
+            for (upstream, m) in snapshot.iter() {
+                let _ = writeln!(
+                    out,
+                    "{name}{{upstream=\"{}\"}} {}",
+                    escape(upstream),
+                    get(m)
+                );
+            }
+        }
+        out
+    }
+}
+
+impl Metrics for MemoryMetrics {
+    fn record_request(&self, upstream: &str, record: &RequestRecord) {
+        self.update(upstream, |m| {
+            *m.requests.entry(status_class(record.status)).or_default() += 1;
+            if let Some(ttfb) = record.ttfb {
+                m.ttfb.observe(ttfb);
+            }
+            m.duration.observe(record.duration);
+            m.bytes_in += record.bytes_in;
+            m.bytes_out += record.bytes_out;
+        })
+    }
+
+    fn record_connect_failure(&self, upstream: &str) {
+        self.update(upstream, |m| m.connect_failures += 1)
+    }
+
+    fn record_stderr(&self, upstream: &str, bytes: usize) {
+        self.update(upstream, |m| m.stderr_bytes += bytes as u64)
+    }
+
+    fn record_pool(&self, upstream: &str, status: PoolStatus) {
+        let worker = thread::current().id();
+        let mut collected = self.lock();
+        let pools = &mut collected.pools;
+        match pools.get_mut(upstream) {
+            Some(workers) => workers.insert(worker, status),
+            None => pools
+                .entry(upstream.to_owned())
+                .or_default()
+                .insert(worker, status),
+        };
+    }
+}
+
+/// Prometheus metrics endpoint for a [`MemoryMetrics`] collector.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use actix_web::{App, web};
+/// use actix_fastcgi::metrics::{MemoryMetrics, prometheus_route};
+///
+/// let metrics = Arc::new(MemoryMetrics::default());
+/// let app = App::new()
+///     .route("/metrics", prometheus_route(metrics));
+/// ```
+pub fn prometheus_route(metrics: Arc<MemoryMetrics>) -> actix_web::Route {
+    actix_web::web::get().to(move || {
+        let body = metrics.render_prometheus();
+        async move {
+            actix_web::HttpResponse::Ok()
+                .content_type("text/plain; version=0.0.4; charset=utf-8")
+                .body(body)
+        }
+    })
+}
+
+#[inline]
+fn status_class(status: Option<StatusCode>) -> &'static str {
+    match status.map(|s| s.as_u16() / 100) {
+        Some(1) => "1xx",
+        Some(2) => "2xx",
+        Some(3) => "3xx",
+        Some(4) => "4xx",
+        Some(5) => "5xx",
+        _ => "error",
+    }
+}
+
+#[inline]
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+#[inline]
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Per-request metrics tracker.
+///
+/// Reports the collected [`RequestRecord`] to the configured [`Metrics`]
+/// when dropped, which happens once the response body is complete.
+pub(crate) struct Tracker {
+    metrics: Arc<dyn Metrics>,
+    upstream: Rc<str>,
+    active: Arc<AtomicUsize>,
+    pool: SockPool,
+    connected: bool,
+    start: Instant,
+    pub(crate) bytes_in: Rc<Cell<u64>>,
+    pub(crate) record: RequestRecord,
+}
+
+impl Tracker {
+    pub(crate) fn new(
+        metrics: Arc<dyn Metrics>,
+        upstream: Rc<str>,
+        active: Arc<AtomicUsize>,
+        pool: SockPool,
+    ) -> Self {
+        Self {
+            metrics,
+            upstream,
+            active,
+            pool,
+            connected: false,
+            start: Instant::now(),
+            bytes_in: Rc::default(),
+            record: RequestRecord::default(),
+        }
+    }
+
+    /// Mark the request as holding a backend connection
+    pub(crate) fn connected(&mut self) {
+        if !self.connected {
+            self.connected = true;
+            self.active.fetch_add(1, Ordering::Relaxed);
+        }
+        self.report_pool();
+    }
+
+    /// Report the current connection pool state
+    pub(crate) fn report_pool(&self) {
+        let status = self.pool.status();
+        let in_use = self.active.load(Ordering::Relaxed);
+        self.metrics.record_pool(
+            &self.upstream,
+            PoolStatus {
+                max_size: status.max_size,
+                size: status.size + in_use,
+                idle: status.available,
+                waiting: status.waiting,
+                in_use,
+            },
+        );
+    }
+
+    #[inline]
+    pub(crate) fn connect_failure(&self) {
+        self.metrics.record_connect_failure(&self.upstream);
+    }
+
+    #[inline]
+    pub(crate) fn stderr(&self, bytes: usize) {
+        self.metrics.record_stderr(&self.upstream, bytes);
+    }
+
+    #[inline]
+    pub(crate) fn stdout(&mut self, bytes: usize) {
+        if self.record.ttfb.is_none() {
+            self.record.ttfb = Some(self.start.elapsed());
+        }
+        self.record.bytes_out += bytes as u64;
+    }
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        if self.connected {
+            self.active.fetch_sub(1, Ordering::Relaxed);
+        }
+        self.record.duration = self.start.elapsed();
+        self.record.bytes_in = self.bytes_in.get();
+        self.metrics.record_request(&self.upstream, &self.record);
+        self.report_pool();
+    }
+}
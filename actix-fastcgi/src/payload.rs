@@ -1,8 +1,10 @@
 //! Stream Abstraction for FastCGI
 
 use std::{
+    cell::Cell,
     io,
     pin::Pin,
+    rc::Rc,
     task::{Context, Poll},
 };
 
@@ -19,20 +21,47 @@ use futures_util::StreamExt;
 use tokio_util::io::StreamReader;
 
 use super::error::Error;
+use super::metrics::Tracker;
 
 const STATUS_HEADER: &str = "Status";
+const ACCEL_BUFFERING_HEADER: &str = "X-Accel-Buffering";
+
+/// FastCGI response body buffering mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Buffering {
+    /// Forward stdout records to the client as soon as they arrive.
+    #[default]
+    Streaming,
+    /// Collect the response body up to the specified number of bytes
+    /// so that a `Content-Length` can be set.
+    ///
+    /// Larger responses fall back to streaming once the limit is exceeded.
+    Buffered(usize),
+}
 
 /// Request Stream wrapper for converting
 /// [`ServiceRequest`](actix_web::dev::ServiceRequest) into
 /// [`StreamReader`](tokio_util::io::StreamReader)
-pub struct RequestStream(LocalBoxStream<'static, Result<Bytes, PayloadError>>);
+pub struct RequestStream {
+    stream: LocalBoxStream<'static, Result<Bytes, PayloadError>>,
+    counter: Option<Rc<Cell<u64>>>,
+}
 
 impl RequestStream {
     pub fn new<S>(stream: S) -> Self
     where
         S: Stream<Item = Result<Bytes, PayloadError>> + 'static,
     {
-        Self(Box::pin(stream))
+        Self {
+            stream: Box::pin(stream),
+            counter: None,
+        }
+    }
+    /// Count the number of bytes read from the stream into the given counter
+    #[inline]
+    pub(crate) fn counted(mut self, counter: Rc<Cell<u64>>) -> Self {
+        self.counter = Some(counter);
+        self
     }
     #[inline]
     pub fn from_request(req: &mut ServiceRequest) -> Self {
@@ -48,9 +77,12 @@ impl Stream for RequestStream {
     type Item = Result<Bytes, std::io::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match Pin::new(&mut self.0).poll_next(cx) {
+        match Pin::new(&mut self.stream).poll_next(cx) {
             Poll::Ready(Some(Ok(data))) => {
                 tracing::trace!("data! {data:?}");
+                if let Some(counter) = self.counter.as_ref() {
+                    counter.set(counter.get() + data.len() as u64);
+                }
                 Poll::Ready(Some(Ok(data)))
             }
             Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(io::Error::other(err)))),
@@ -67,6 +99,8 @@ pub struct ResponseStream {
     stream: LocalBoxStream<'static, Result<Content, ClientError>>,
     buf: BytesMut,
     eof: Option<usize>,
+    buffering: Buffering,
+    tracker: Option<Tracker>,
 }
 
 impl ResponseStream {
@@ -78,9 +112,28 @@ impl ResponseStream {
             stream: Box::pin(stream),
             buf: BytesMut::with_capacity(1_024), // pre-allocate 1KiB
             eof: None,
+            buffering: Buffering::default(),
+            tracker: None,
         }
     }
 
+    /// Configure the response body [`Buffering`] mode
+    ///
+    /// A backend `X-Accel-Buffering: no` response header always
+    /// switches the response to [`Buffering::Streaming`].
+    #[inline]
+    pub fn buffering(mut self, buffering: Buffering) -> Self {
+        self.buffering = buffering;
+        self
+    }
+
+    /// Report response metrics to the given tracker
+    #[inline]
+    pub(crate) fn tracked(mut self, tracker: Option<Tracker>) -> Self {
+        self.tracker = tracker;
+        self
+    }
+
     #[inline]
     async fn read_until_body(&mut self) -> Result<(), Error> {
         while self.eof.is_none() {
@@ -92,11 +145,28 @@ impl ResponseStream {
         Ok(())
     }
 
+    /// Read the remaining response body into memory
+    ///
+    /// Returns `None` and leaves the collected data to be streamed
+    /// if the body exceeds the specified limit.
+    async fn read_body(&mut self, limit: usize) -> Result<Option<Bytes>, Error> {
+        let mut body = BytesMut::new();
+        while let Some(data) = self.next().await {
+            body.extend_from_slice(&data?);
+            if body.len() > limit {
+                self.buf = body;
+                return Ok(None);
+            }
+        }
+        Ok(Some(body.freeze()))
+    }
+
     /// Convert Stream Buffer into HttpResponse
     ///
     /// Internally writes stream stdout to temporary memory-buffer
-    /// until all headers can be read, then passes the rest of the stream
-    /// body directly until final EOF is reached.
+    /// until all headers can be read. The rest of the body is either
+    /// collected into memory or passed through directly until final EOF
+    /// is reached depending on the configured [`Buffering`] mode.
     pub async fn into_response(mut self) -> Result<HttpResponse, Error> {
         self.read_until_body().await?;
 
@@ -105,17 +175,34 @@ impl ResponseStream {
         httparse::parse_headers(&raw_headers[..raw_headers.len() - 2], &mut headers)
             .map_err(Error::InvalidHeaders)?;
 
+        let mut status = StatusCode::OK;
+        let mut buffering = self.buffering;
         let mut builder = HttpResponse::Ok();
         for header in headers.into_iter().filter(|h| !h.name.is_empty()) {
             match header.name {
                 STATUS_HEADER => {
                     let mut split = header.value.split(|b| b.is_ascii_whitespace());
-                    builder.status(StatusCode::from_bytes(split.next().unwrap_or(b""))?)
+                    status = StatusCode::from_bytes(split.next().unwrap_or(b""))?;
+                    builder.status(status)
+                }
+                name if name.eq_ignore_ascii_case(ACCEL_BUFFERING_HEADER) => {
+                    if header.value.eq_ignore_ascii_case(b"no") {
+                        buffering = Buffering::Streaming;
+                    }
+                    continue;
                 }
                 name => builder.append_header((name, header.value)),
             };
         }
 
+        if let Some(tracker) = self.tracker.as_mut() {
+            tracker.record.status = Some(status);
+        }
+        if let Buffering::Buffered(limit) = buffering
+            && let Some(body) = self.read_body(limit).await?
+        {
+            return Ok(builder.body(body));
+        }
         Ok(builder.streaming(self))
     }
 }
@@ -124,20 +211,27 @@ impl Stream for ResponseStream {
     type Item = Result<Bytes, PayloadError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if !self.buf.is_empty() {
+        if self.eof.is_some() && !self.buf.is_empty() {
             let idx = self.buf.len();
             return Poll::Ready(Some(Ok(self.buf.split_to(idx).freeze())));
         }
         match Pin::new(&mut self.stream).poll_next(cx) {
             Poll::Ready(Some(Ok(content))) => match content {
                 Content::Stdout(data) => {
-                    if self.eof.is_none() {
-                        self.buf.extend_from_slice(&data);
-                        self.eof = data.windows(4).position(|w| w == b"\r\n\r\n");
+                    if let Some(tracker) = self.tracker.as_mut() {
+                        tracker.stdout(data.len());
                     }
-                    Poll::Ready(Some(Ok(data)))
+                    if self.eof.is_some() {
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+                    self.buf.extend_from_slice(&data);
+                    self.eof = self.buf.windows(4).position(|w| w == b"\r\n\r\n");
+                    Poll::Ready(Some(Ok(Bytes::new())))
                 }
                 Content::Stderr(data) => {
+                    if let Some(tracker) = self.tracker.as_ref() {
+                        tracker.stderr(data.len());
+                    }
                     let message = std::str::from_utf8(&data);
                     tracing::warn!("FastCGI Stderr {message:?}");
                     Poll::Ready(Some(Ok(Bytes::new())))
@@ -146,10 +240,7 @@ impl Stream for ResponseStream {
             Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(PayloadError::Io(
                 io::Error::other(err.to_string()),
             )))),
-            Poll::Ready(None) => {
-                self.eof = Some(self.buf.len());
-                Poll::Ready(None)
-            }
+            Poll::Ready(None) => Poll::Ready(None),
             Poll::Pending => Poll::Pending,
         }
     }
@@ -2,23 +2,25 @@ use std::{
     ops::Deref,
     path::{Path, PathBuf},
     rc::Rc,
+    sync::{Arc, atomic::AtomicUsize},
 };
 
-use actix_files::PathBufWrap;
+use actix_files::{Directory, PathBufWrap};
 use actix_web::{
-    HttpRequest,
+    HttpRequest, HttpResponse,
     body::BoxBody,
     dev::{self, Service, ServiceRequest, ServiceResponse},
     error::Error as ActixError,
+    http::header,
 };
-use deadpool::managed::Object;
+use deadpool::managed::{Object, PoolError};
 use fastcgi_client::{Client, Params, Request};
 use futures_core::future::LocalBoxFuture;
 
-use crate::{SockPool, pool};
+use crate::{Metrics, SockPool, directory::DirectoryRenderer, metrics::Tracker, pool};
 
 use super::error::Error;
-use super::payload::{RequestStream, ResponseStream};
+use super::payload::{Buffering, RequestStream, ResponseStream};
 
 /// Assembled fastcgi client service
 #[derive(Clone)]
@@ -36,12 +38,7 @@ impl FastCGIService {
     pub fn fill_params<'a>(&'a self, path: &Path, req: &HttpRequest) -> Params<'a> {
         let mut real_path = self.root.join(path);
         if real_path.is_dir() {
-            real_path = self
-                .indexes
-                .iter()
-                .map(|index| real_path.join(index))
-                .find(|path| path.exists())
-                .unwrap_or(real_path);
+            real_path = self.find_index(&real_path).unwrap_or(real_path);
         }
 
         let root = self.root.to_string_lossy().to_string();
@@ -92,7 +89,66 @@ impl Deref for FastCGIService {
 pub struct FastCGIInner {
     pub(crate) root: PathBuf,
     pub(crate) indexes: Vec<String>,
+    pub(crate) show_index: bool,
+    pub(crate) redirect_to_slash: bool,
+    pub(crate) renderer: Rc<DirectoryRenderer>,
+    pub(crate) buffering: Buffering,
     pub(crate) fastcgi_pool: SockPool,
+    pub(crate) upstream: Rc<str>,
+    pub(crate) metrics: Option<Arc<dyn Metrics>>,
+    pub(crate) active: Arc<AtomicUsize>,
+}
+
+impl FastCGIInner {
+    /// Find the first configured index file that exists within the directory
+    fn find_index(&self, dir: &Path) -> Option<PathBuf> {
+        self.indexes
+            .iter()
+            .map(|index| dir.join(index))
+            .find(|path| path.exists())
+    }
+
+    /// Respond to directory requests that cannot be passed to the FastCGI backend
+    ///
+    /// Returns the original request if it should continue to the backend.
+    fn handle_directory(
+        &self,
+        req: ServiceRequest,
+        dir: PathBuf,
+    ) -> Result<ServiceResponse, ServiceRequest> {
+        if self.redirect_to_slash && !req.path().ends_with('/') {
+            let redirect_to = match req.query_string() {
+                "" => format!("{}/", req.path()),
+                query => format!("{}/?{query}", req.path()),
+            };
+            return Ok(req.into_response(
+                HttpResponse::MovedPermanently()
+                    .insert_header((header::LOCATION, redirect_to))
+                    .finish(),
+            ));
+        }
+        if self.find_index(&dir).is_some() {
+            return Err(req);
+        }
+        let (http_req, _) = req.into_parts();
+        if !self.show_index {
+            return Ok(ServiceResponse::from_err(Error::IsDirectory, http_req));
+        }
+        let dir = Directory::new(self.root.clone(), dir);
+        Ok((self.renderer)(&dir, &http_req)
+            .unwrap_or_else(|err| ServiceResponse::from_err(Error::Io(err), http_req)))
+    }
+
+    /// Build a new metrics tracker when metrics are enabled
+    fn tracker(&self) -> Option<Tracker> {
+        let metrics = self.metrics.clone()?;
+        Some(Tracker::new(
+            metrics,
+            self.upstream.clone(),
+            self.active.clone(),
+            self.fastcgi_pool.clone(),
+        ))
+    }
 }
 
 impl Service<ServiceRequest> for FastCGIService {
@@ -107,13 +163,40 @@ impl Service<ServiceRequest> for FastCGIService {
         Box::pin(async move {
             let path_on_disk = PathBufWrap::parse_req(req.request(), false)
                 .inspect_err(|err| tracing::error!("invalid request: {err:?}"))?;
+
+            let real_path = this.root.join(&path_on_disk);
+            if real_path.is_dir() {
+                req = match this.handle_directory(req, real_path) {
+                    Ok(res) => return Ok(res),
+                    Err(req) => req,
+                };
+            }
             let params = this.fill_params(path_on_disk.as_ref(), req.request());
 
-            let obj = this.fastcgi_pool.get().await.unwrap();
+            let mut tracker = this.tracker();
+            let obj = this.fastcgi_pool.get().await.map_err(|err| match err {
+                PoolError::Backend(err) => {
+                    if let Some(tracker) = tracker.as_ref() {
+                        tracker.connect_failure();
+                    }
+                    tracing::error!("connection failed: {err:?}");
+                    err
+                }
+                err => {
+                    tracing::error!("connection pool error: {err:?}");
+                    Error::PoolUnavailable
+                }
+            })?;
             let sock = Object::<pool::Manager>::take(obj);
             let client = Client::new(sock);
-
-            let stream = RequestStream::from_request(&mut req);
+            if let Some(tracker) = tracker.as_mut() {
+                tracker.connected();
+            }
+
+            let mut stream = RequestStream::from_request(&mut req);
+            if let Some(tracker) = tracker.as_ref() {
+                stream = stream.counted(tracker.bytes_in.clone());
+            }
             let request = Request::new(params, stream.into_reader());
 
             let stream = client
@@ -123,6 +206,8 @@ impl Service<ServiceRequest> for FastCGIService {
                 .inspect_err(|err| tracing::error!("request error: {err:?}"))?;
 
             let http_res = ResponseStream::new(stream)
+                .buffering(this.buffering)
+                .tracked(tracker)
                 .into_response()
                 .await
                 .inspect_err(|err| tracing::error!("invalid response: {err:?}"))?;
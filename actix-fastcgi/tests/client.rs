@@ -2,8 +2,10 @@
 
 use std::collections::HashMap;
 
+use actix_fastcgi::{Buffering, FastCGI};
 use actix_web::{
-    body,
+    App,
+    body::{self, BodySize, MessageBody},
     http::header,
     test::{self, TestRequest},
 };
@@ -87,3 +89,38 @@ async fn test_simple_json() {
     let body = std::str::from_utf8(&data).expect("invalid body");
     assert_eq!(body, "Hello World!");
 }
+
+#[actix_web::test]
+async fn test_buffered_response() {
+    setup();
+
+    let fgi = FastCGI::new("", "tests/php", "127.0.0.1:9000").buffering(Buffering::Buffered(1024));
+    let srv = test::init_service(App::new().service(fgi)).await;
+    let req = TestRequest::with_uri("/hello.php").to_request();
+    let res = test::call_service(&srv, req).await;
+
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(res.response().body().size(), BodySize::Sized(12));
+
+    let content = res.into_body();
+    let data = body::to_bytes(content).await.expect("missing body");
+    assert_eq!(&data[..], b"Hello World!");
+}
+
+#[actix_web::test]
+async fn test_accel_buffering_response() {
+    setup();
+
+    let fgi = FastCGI::new("", "tests/php", "127.0.0.1:9000").buffering(Buffering::Buffered(1024));
+    let srv = test::init_service(App::new().service(fgi)).await;
+    let req = TestRequest::with_uri("/stream.php").to_request();
+    let res = test::call_service(&srv, req).await;
+
+    assert_eq!(res.status().to_string(), "200 OK");
+    assert_eq!(res.response().body().size(), BodySize::Stream);
+    assert_eq!(res.headers().get("X-Accel-Buffering"), None);
+
+    let content = res.into_body();
+    let data = body::to_bytes(content).await.expect("missing body");
+    assert_eq!(&data[..], b"Hello Stream!");
+}
@@ -0,0 +1,53 @@
+//! FastCGI Directory Handling Tests
+
+use actix_fastcgi::FastCGI;
+use actix_web::{
+    App, body,
+    http::{StatusCode, header},
+    test::{self, TestRequest},
+};
+
+mod common;
+use common::*;
+
+#[actix_web::test]
+async fn test_directory_redirect() {
+    setup();
+
+    let srv = spawn_test_server!();
+    let req = TestRequest::with_uri("/listing?a=b").to_request();
+    let res = test::call_service(&srv, req).await;
+
+    assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        res.headers().get(header::LOCATION).map(|v| v.to_str().unwrap()),
+        Some("/listing/?a=b")
+    );
+}
+
+#[actix_web::test]
+async fn test_directory_forbidden() {
+    setup();
+
+    let srv = spawn_test_server!();
+    let req = TestRequest::with_uri("/listing/").to_request();
+    let res = test::call_service(&srv, req).await;
+
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn test_directory_listing() {
+    setup();
+
+    let fgi = FastCGI::new("", "tests/php", "127.0.0.1:9000").show_files_listing();
+    let srv = test::init_service(App::new().service(fgi)).await;
+    let req = TestRequest::with_uri("/listing/").to_request();
+    let res = test::call_service(&srv, req).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let data = body::to_bytes(res.into_body()).await.expect("missing body");
+    let body = std::str::from_utf8(&data).expect("invalid body");
+    assert!(body.contains("<title>Index of /listing/</title>"));
+    assert!(body.contains("<a href=\"/listing/hello.txt\">hello.txt</a>"));
+}
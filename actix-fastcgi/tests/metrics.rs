@@ -0,0 +1,99 @@
+//! FastCGI Metrics Tests
+
+use std::{sync::Arc, thread};
+
+use actix_fastcgi::{FastCGI, MemoryMetrics, Metrics, metrics::PoolStatus};
+use actix_web::{
+    App, body,
+    test::{self, TestRequest},
+};
+
+mod common;
+use common::*;
+
+#[actix_web::test]
+async fn test_request_metrics() {
+    setup();
+
+    let metrics = Arc::new(MemoryMetrics::default());
+    let fgi = FastCGI::new("", "tests/php", "127.0.0.1:9000").metrics(metrics.clone());
+    let srv = test::init_service(App::new().service(fgi)).await;
+
+    let req = TestRequest::with_uri("/hello.php").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status().to_string(), "200 OK");
+    body::to_bytes(res.into_body()).await.expect("missing body");
+
+    let snapshot = metrics.snapshot();
+    let upstream = snapshot.get("127.0.0.1:9000").expect("missing upstream");
+    assert_eq!(upstream.requests.get("2xx"), Some(&1));
+    assert_eq!(upstream.duration.count, 1);
+    assert_eq!(upstream.ttfb.count, 1);
+    assert!(upstream.bytes_out > 0);
+    assert_eq!(upstream.pool.in_use, 0);
+
+    let text = metrics.render_prometheus();
+    assert!(text.contains("fastcgi_requests_total{upstream=\"127.0.0.1:9000\",class=\"2xx\"} 1"));
+    assert!(text.contains("fastcgi_request_duration_seconds_count{upstream=\"127.0.0.1:9000\"} 1"));
+}
+
+#[actix_web::test]
+async fn test_connect_failure_metrics() {
+    setup();
+
+    let metrics = Arc::new(MemoryMetrics::default());
+    let fgi = FastCGI::new("", "tests/php", "127.0.0.1:1").metrics(metrics.clone());
+    let srv = test::init_service(App::new().service(fgi)).await;
+
+    let req = TestRequest::with_uri("/hello.php").to_request();
+    let err = test::try_call_service(&srv, req)
+        .await
+        .expect_err("request succeeded");
+    assert_eq!(
+        err.as_response_error().status_code().to_string(),
+        "500 Internal Server Error"
+    );
+
+    let snapshot = metrics.snapshot();
+    let upstream = snapshot.get("127.0.0.1:1").expect("missing upstream");
+    assert_eq!(upstream.connect_failures, 1);
+    assert_eq!(upstream.requests.get("error"), Some(&1));
+    assert_eq!(upstream.pool.in_use, 0);
+    assert_eq!(upstream.pool.size, 0);
+}
+
+#[test]
+fn test_pool_metrics_workers() {
+    setup();
+
+    // every worker reports the state of its own pool
+    let metrics = Arc::new(MemoryMetrics::default());
+    let status = PoolStatus {
+        max_size: 8,
+        size: 3,
+        idle: 1,
+        waiting: 0,
+        in_use: 2,
+    };
+    metrics.record_pool("127.0.0.1:9000", status);
+    let worker = Arc::clone(&metrics);
+    thread::spawn(move || worker.record_pool("127.0.0.1:9000", status))
+        .join()
+        .expect("worker panicked");
+    metrics.record_pool(
+        "127.0.0.1:9000",
+        PoolStatus {
+            in_use: 1,
+            ..status
+        },
+    );
+
+    let snapshot = metrics.snapshot();
+    let upstream = snapshot.get("127.0.0.1:9000").expect("missing upstream");
+    assert_eq!(upstream.pool.max_size, 16);
+    assert_eq!(upstream.pool.size, 6);
+    assert_eq!(upstream.pool.in_use, 3);
+
+    let text = metrics.render_prometheus();
+    assert!(text.contains("fastcgi_pool_in_use{upstream=\"127.0.0.1:9000\"} 3"));
+}
@@ -0,0 +1,4 @@
+<?php
+  header('X-Accel-Buffering: no');
+  echo "Hello Stream!";
+?>
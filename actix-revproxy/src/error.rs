@@ -1,7 +1,10 @@
 //! Error and Result Types
 
-use actix_web::{ResponseError, error::QueryPayloadError};
-use awc::http::header::{InvalidHeaderValue, ToStrError};
+use actix_web::{ResponseError, http::StatusCode};
+use awc::{
+    error::{ConnectError, SendRequestError, WsClientError},
+    http::header::{InvalidHeaderValue, ToStrError},
+};
 use derive_more::{Display, Error, From};
 
 /// Errors which occur when processing Reverse Proxy Requests/Responses
@@ -12,7 +15,14 @@ pub enum Error {
     Io(std::io::Error),
 
     /// Proxy client request failed
-    FailedRequest(awc::error::SendRequestError),
+    FailedRequest(SendRequestError),
+
+    /// Proxy client websocket upgrade failed
+    FailedUpgrade(WsClientError),
+
+    /// No upstream is currently available to serve the request
+    #[display("No healthy upstream available")]
+    NoHealthyUpstream,
 
     /// Failed to parse value during header processing
     InvalidHeader(ToStrError),
@@ -27,32 +37,49 @@ pub enum Error {
 /// Errors which occur when building a combined proxied request uri
 #[derive(Debug, Display, From, Error)]
 pub enum UriError {
-    #[display("Failed to combine URI paths")]
-    InvalidUriPath,
-
     #[display("Missing proxy url authority")]
     MissingAuthority,
 
-    #[display("Invalid query string supplied")]
-    InvalidQuery(QueryPayloadError),
-
-    #[display("Failed to encode combined query string")]
-    QueryEncoderError(serde_urlencoded::ser::Error),
-
     #[display("Failed to build http request uri")]
     RequestError(awc::error::HttpError),
+
+    #[display("Invalid upstream uri")]
+    InvalidUri(awc::http::uri::InvalidUri),
+
+    #[display("Missing unix socket path")]
+    MissingSocketPath,
+}
+
+/// Map a failed upstream request to the matching gateway status
+fn send_status(err: &SendRequestError) -> StatusCode {
+    match err {
+        SendRequestError::Timeout | SendRequestError::Connect(ConnectError::Timeout) => {
+            StatusCode::GATEWAY_TIMEOUT
+        }
+        SendRequestError::Url(_) | SendRequestError::Http(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::BAD_GATEWAY,
+    }
 }
 
 impl ResponseError for Error {
-    /// Returns `500 Internal Server Error`.
-    fn status_code(&self) -> actix_web::http::StatusCode {
-        actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+    /// Returns `502 Bad Gateway` when the upstream could not be reached or
+    /// sent an invalid response, `504 Gateway Timeout` when the upstream
+    /// timed out, `503 Service Unavailable` when no upstream is available,
+    /// and `500 Internal Server Error` otherwise.
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::FailedRequest(err) => send_status(err),
+            Self::FailedUpgrade(WsClientError::SendRequest(err)) => send_status(err),
+            Self::FailedUpgrade(_) => StatusCode::BAD_GATEWAY,
+            Self::NoHealthyUpstream => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 }
 
 impl ResponseError for UriError {
     /// Returns `500 Internal Server Error`.
-    fn status_code(&self) -> actix_web::http::StatusCode {
-        actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
     }
 }
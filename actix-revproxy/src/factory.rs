@@ -1,18 +1,23 @@
-use std::{fmt::Debug, rc::Rc, str::FromStr};
+use std::{fmt::Debug, rc::Rc, str::FromStr, time::Duration};
 
 use actix_service::ServiceFactory;
 use actix_web::{
-    Error,
+    Error, HttpRequest, HttpResponse,
     dev::{AppService, HttpServiceFactory, ResourceDef, ServiceRequest, ServiceResponse},
     guard::Guard,
+    http::StatusCode,
+    web::Bytes,
 };
-use awc::{
-    Client,
-    http::{Uri, header},
-};
+use awc::{Client, http::header};
 use futures_core::future::LocalBoxFuture;
 
+use crate::forward::{ForwardPolicy, Forwarding, parse_network};
+use crate::pages::ErrorPages;
+use crate::reverse::ReverseRules;
 use crate::service::HeaderVec;
+use crate::stream::UpstreamUri;
+use crate::upgrade::Limits;
+use crate::upstream::{Balance, Ejection, HealthCheck, Pool, Upstream, spawn_health_checks};
 
 use super::service::{ProxyService, ProxyServiceInner};
 
@@ -34,10 +39,20 @@ pub struct RevProxy {
     mount_path: String,
     guards: Vec<Rc<dyn Guard>>,
     client: Rc<Client>,
-    resolve: Uri,
+    upstreams: Vec<Upstream>,
+    balance: Balance,
+    health_check: Option<HealthCheck>,
+    ejection: Option<Ejection>,
+    max_retries: Option<usize>,
     change_host: bool,
+    strip_prefix: bool,
+    forwarding: ForwardPolicy,
     header_up: HeaderVec,
     header_down: HeaderVec,
+    reverse: ReverseRules,
+    reverse_upstreams: bool,
+    websocket: Limits,
+    errors: ErrorPages,
 }
 
 impl RevProxy {
@@ -49,18 +64,57 @@ impl RevProxy {
     ///
     /// The second argument (`uri`) is the base uri that directs where the proxy
     /// resolves at.
-    pub fn new<U: TryInto<Uri>>(mount_path: &str, uri: U) -> Self
+    pub fn new<U: TryInto<UpstreamUri>>(mount_path: &str, uri: U) -> Self
     where
         U::Error: Debug,
     {
+        Self::pool(mount_path, [Upstream::new(uri)])
+    }
+
+    /// Creates new `RevProxy` instance balancing between a pool of upstreams
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use actix_web::App;
+    /// use actix_revproxy::{Balance, HealthCheck, RevProxy, Upstream};
+    ///
+    /// let proxy = RevProxy::pool("/", [
+    ///     Upstream::new("http://127.0.0.1:8080").weight(2),
+    ///     Upstream::new("http://127.0.0.1:8081"),
+    /// ])
+    /// .balance(Balance::LeastConnections)
+    /// .health_check(HealthCheck::new("/healthz"))
+    /// .passive_health_check(3, Duration::from_secs(30));
+    ///
+    /// let app = App::new().service(proxy);
+    /// ```
+    pub fn pool<I>(mount_path: &str, upstreams: I) -> Self
+    where
+        I: IntoIterator<Item = Upstream>,
+    {
+        let upstreams: Vec<Upstream> = upstreams.into_iter().collect();
+        assert!(!upstreams.is_empty(), "empty upstream pool");
         Self {
             mount_path: mount_path.to_owned(),
             guards: Vec::new(),
             client: Rc::new(awc::Client::new()),
-            resolve: uri.try_into().expect("invalid resolution uri"),
+            upstreams,
+            balance: Balance::default(),
+            health_check: None,
+            ejection: None,
+            max_retries: None,
             change_host: false,
+            strip_prefix: false,
+            forwarding: ForwardPolicy::default(),
             header_up: Vec::new(),
             header_down: Vec::new(),
+            reverse: ReverseRules::default(),
+            reverse_upstreams: false,
+            websocket: Limits::default(),
+            errors: ErrorPages::default(),
         }
     }
 
@@ -89,19 +143,111 @@ impl RevProxy {
     /// Overrides the actix-web-client instance used by the proxy
     ///
     /// Default is [`Client::new()`](awc::Client::new)
+    ///
+    /// The client is only used for tcp upstreams, while every unix socket
+    /// upstream is requested by its own default client.
     pub fn with_client(mut self, client: Client) -> Self {
         self.client = Rc::new(client);
         self
     }
 
+    /// Configure the load balancing strategy between upstreams
+    ///
+    /// Default is [`Balance::RoundRobin`].
+    pub fn balance(mut self, balance: Balance) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// Enable active health checks against every upstream
+    pub fn health_check(mut self, check: HealthCheck) -> Self {
+        self.health_check = Some(check);
+        self
+    }
+
+    /// Eject an upstream from the pool for the given duration after
+    /// `max_failures` consecutive failed requests.
+    ///
+    /// Requests fail when the upstream cannot be reached or responds with
+    /// a `5xx` status. Failures are counted by every worker independently.
+    ///
+    /// Default is to never eject upstreams.
+    pub fn passive_health_check(mut self, max_failures: u32, duration: Duration) -> Self {
+        self.ejection = Some(Ejection {
+            max_failures: max_failures.max(1),
+            duration,
+        });
+        self
+    }
+
+    /// Configure how many times an idempotent request without a body is
+    /// retried on a different upstream when the connection fails.
+    ///
+    /// Default is to try every upstream in the pool once.
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.max_retries = Some(retries);
+        self
+    }
+
     /// Configure proxy to change hostname to the upstream host
     ///
+    /// Unix socket upstreams have no hostname and always receive the
+    /// established hostname of the original request.
+    ///
     /// Default is return the established hostname of the original request.
     pub fn change_host(mut self) -> Self {
         self.change_host = true;
         self
     }
 
+    /// Configure proxy to remove the matched mount path from the request
+    /// path before appending it to the upstream uri.
+    ///
+    /// Default is to append the full request path.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_revproxy::RevProxy;
+    ///
+    /// // `/app/users?id=1` is proxied to `http://127.0.0.1:8080/api/users?id=1`
+    /// RevProxy::new("/app", "http://127.0.0.1:8080/api").strip_prefix();
+    /// ```
+    pub fn strip_prefix(mut self) -> Self {
+        self.strip_prefix = true;
+        self
+    }
+
+    /// Configure which forwarding headers are sent to the upstream
+    ///
+    /// Default is [`Forwarding::Legacy`].
+    pub fn forwarding(mut self, mode: Forwarding) -> Self {
+        self.forwarding.mode = mode;
+        self
+    }
+
+    /// Trust forwarding headers sent by a proxy address or CIDR network.
+    ///
+    /// Forwarding headers from trusted proxies are kept and extended with
+    /// the connection details of the proxy, while headers from any other
+    /// peer are replaced. By default no proxies are trusted.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_revproxy::{Forwarding, RevProxy};
+    ///
+    /// RevProxy::new("/", "http://127.0.0.1:8080")
+    ///     .forwarding(Forwarding::Both)
+    ///     .trusted_proxy("10.0.0.0/8")
+    ///     .trusted_proxy("::1");
+    /// ```
+    pub fn trusted_proxy(mut self, network: &str) -> Self {
+        match parse_network(network) {
+            Some(network) => self.forwarding.trusted.push(network),
+            None => tracing::warn!("invalid trusted proxy {network:?}"),
+        }
+        self
+    }
+
     /// Append a header to include in the upstream request.
     pub fn upstream_header(mut self, name: &str, value: &str) -> Self {
         let Ok(name) = header::HeaderName::from_str(name) else {
@@ -129,6 +275,130 @@ impl RevProxy {
         self.header_down.push((name, value));
         self
     }
+
+    /// Rewrite `Location`, `Content-Location`, `URI` and `Refresh` response
+    /// headers pointing at the upstream `url` to point at `path` on the
+    /// client-facing host instead.
+    ///
+    /// Equivalent to Apache `ProxyPassReverse`.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_revproxy::RevProxy;
+    ///
+    /// // `Location: http://10.0.0.5:8080/login` becomes `Location: https://example.com/app/login`
+    /// RevProxy::new("/app", "http://10.0.0.5:8080")
+    ///     .proxy_pass_reverse("/app", "http://10.0.0.5:8080");
+    /// ```
+    pub fn proxy_pass_reverse(mut self, path: &str, url: &str) -> Self {
+        self.reverse.urls.push((path.to_owned(), url.to_owned()));
+        self
+    }
+
+    /// Apply [`RevProxy::proxy_pass_reverse`] from every upstream uri
+    /// to the mount path of the proxy when combined with
+    /// [`RevProxy::strip_prefix`], or to the root path otherwise.
+    ///
+    /// Unix socket upstreams receive the client-facing host, so their urls
+    /// on that host are mapped to the mount path instead.
+    pub fn proxy_pass_reverse_upstreams(mut self) -> Self {
+        self.reverse_upstreams = true;
+        self
+    }
+
+    /// Rewrite the `Domain` attribute of upstream `Set-Cookie` headers from
+    /// the `internal` domain to the `public` domain. An empty `public`
+    /// domain removes the attribute.
+    ///
+    /// Equivalent to Apache `ProxyPassReverseCookieDomain`.
+    pub fn proxy_pass_reverse_cookie_domain(mut self, internal: &str, public: &str) -> Self {
+        self.reverse
+            .cookie_domains
+            .push((internal.to_owned(), public.to_owned()));
+        self
+    }
+
+    /// Rewrite the `Path` attribute of upstream `Set-Cookie` headers
+    /// starting with the `internal` path to start with the `public` path.
+    ///
+    /// Equivalent to Apache `ProxyPassReverseCookiePath`.
+    pub fn proxy_pass_reverse_cookie_path(mut self, internal: &str, public: &str) -> Self {
+        self.reverse
+            .cookie_paths
+            .push((internal.to_owned(), public.to_owned()));
+        self
+    }
+
+    /// Configure how long a proxied WebSocket connection may go without
+    /// traffic in either direction before it is closed.
+    ///
+    /// Default is 60 seconds.
+    pub fn websocket_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.websocket.idle_timeout = timeout;
+        self
+    }
+
+    /// Configure the maximum size of a single WebSocket message in either
+    /// direction. The connection is closed when a message exceeds the limit.
+    ///
+    /// Default is unlimited.
+    pub fn websocket_max_message_size(mut self, size: usize) -> Self {
+        self.websocket.max_message_size = Some(size);
+        self
+    }
+
+    /// Serve a static HTML page instead of the default error response
+    /// for the given status.
+    ///
+    /// Pages are served for proxy failures such as `502 Bad Gateway`,
+    /// `503 Service Unavailable` and `504 Gateway Timeout`, as well as
+    /// upstream responses replaced by [`RevProxy::intercept_errors`].
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::{App, http::StatusCode};
+    /// use actix_revproxy::RevProxy;
+    ///
+    /// App::new().service(
+    ///     RevProxy::new("/", "http://127.0.0.1:8080")
+    ///         .error_page(StatusCode::BAD_GATEWAY, "<h1>Be right back</h1>")
+    /// );
+    /// ```
+    pub fn error_page(mut self, status: StatusCode, body: impl Into<Bytes>) -> Self {
+        self.errors.pages.retain(|(code, _)| *code != status);
+        self.errors.pages.push((status, body.into()));
+        self
+    }
+
+    /// Render error responses for statuses without an
+    /// [`error page`](RevProxy::error_page) using a custom handler.
+    ///
+    /// # Examples
+    /// ```
+    /// use actix_web::{App, HttpResponse};
+    /// use actix_revproxy::RevProxy;
+    ///
+    /// App::new().service(
+    ///     RevProxy::new("/", "http://127.0.0.1:8080").error_handler(|status, _req| {
+    ///         HttpResponse::build(status).body(format!("proxy error: {status}"))
+    ///     })
+    /// );
+    /// ```
+    pub fn error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(StatusCode, &HttpRequest) -> HttpResponse + 'static,
+    {
+        self.errors.handler = Some(Rc::new(handler));
+        self
+    }
+
+    /// Replace `5xx` responses from the upstream with the configured
+    /// error page or handler, or with a plain status message, so the
+    /// upstream error body never reaches the client.
+    pub fn intercept_errors(mut self) -> Self {
+        self.errors.intercept = true;
+        self
+    }
 }
 
 impl HttpServiceFactory for RevProxy {
@@ -164,12 +434,42 @@ impl ServiceFactory<ServiceRequest> for RevProxy {
     type Future = LocalBoxFuture<'static, Result<Self::Service, Self::InitError>>;
 
     fn new_service(&self, _: ()) -> Self::Future {
+        let pool = Rc::new(Pool::new(
+            &self.upstreams,
+            self.balance.clone(),
+            self.ejection,
+        ));
+        if let Some(check) = self.health_check.clone() {
+            spawn_health_checks(&pool, self.client.clone(), check);
+        }
+        let mut reverse = self.reverse.clone();
+        if self.reverse_upstreams {
+            let path = match self.strip_prefix {
+                true => self.mount_path.as_str(),
+                false => "",
+            };
+            for upstream in self.upstreams.iter() {
+                // unix socket upstreams build their urls from the client host
+                if upstream.uri().socket().is_some() {
+                    reverse.client_path = Some(path.to_owned());
+                    continue;
+                }
+                let url = upstream.uri().to_string();
+                reverse.urls.push((path.to_owned(), url));
+            }
+        }
         let inner = ProxyServiceInner {
             client: self.client.clone(),
-            resolve: self.resolve.clone(),
+            max_retries: self.max_retries.unwrap_or(pool.len() - 1),
+            pool,
             change_host: self.change_host,
+            strip_prefix: self.strip_prefix,
+            forwarding: self.forwarding.clone(),
             header_up: self.header_up.clone(),
             header_down: self.header_down.clone(),
+            reverse,
+            websocket: self.websocket,
+            errors: self.errors.clone(),
         };
         Box::pin(async move { Ok(ProxyService(Rc::new(inner))) })
     }
@@ -0,0 +1,172 @@
+//! Forwarding Header Policy
+use std::net::IpAddr;
+
+use actix_web::HttpRequest;
+use awc::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+
+use crate::error::Error;
+use crate::proxy::update_forwarded;
+
+const X_FORWARDED_PORT: HeaderName = HeaderName::from_static("x-forwarded-port");
+const X_FORWARDED_PREFIX: HeaderName = HeaderName::from_static("x-forwarded-prefix");
+
+const LEGACY_HEADERS: [HeaderName; 5] = [
+    header::X_FORWARDED_FOR,
+    header::X_FORWARDED_HOST,
+    header::X_FORWARDED_PROTO,
+    X_FORWARDED_PORT,
+    X_FORWARDED_PREFIX,
+];
+
+/// Forwarding headers sent to the upstream
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Forwarding {
+    /// Legacy `X-Forwarded-For`, `X-Forwarded-Host`, `X-Forwarded-Proto`,
+    /// `X-Forwarded-Port` and `X-Forwarded-Prefix` headers
+    #[default]
+    Legacy,
+    /// RFC 7239 `Forwarded` header
+    Standard,
+    /// Both legacy and RFC 7239 headers
+    Both,
+    /// No forwarding headers
+    None,
+}
+
+impl Forwarding {
+    #[inline]
+    fn legacy(&self) -> bool {
+        matches!(self, Self::Legacy | Self::Both)
+    }
+
+    #[inline]
+    fn standard(&self) -> bool {
+        matches!(self, Self::Standard | Self::Both)
+    }
+}
+
+/// Forwarding mode along with the proxies trusted to supply forwarding headers
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ForwardPolicy {
+    pub(crate) mode: Forwarding,
+    pub(crate) trusted: Vec<(IpAddr, u8)>,
+}
+
+impl ForwardPolicy {
+    /// Check if the peer address belongs to a trusted proxy
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted.iter().any(|(net, bits)| match (ip, net) {
+            (IpAddr::V4(ip), IpAddr::V4(net)) => {
+                let mask = u32::MAX.checked_shl(32 - *bits as u32).unwrap_or(0);
+                u32::from(ip) & mask == u32::from(*net) & mask
+            }
+            (IpAddr::V6(ip), IpAddr::V6(net)) => {
+                let mask = u128::MAX.checked_shl(128 - *bits as u32).unwrap_or(0);
+                u128::from(ip) & mask == u128::from(*net) & mask
+            }
+            _ => false,
+        })
+    }
+
+    /// Update the forwarding headers of the upstream request.
+    ///
+    /// Incoming forwarding headers are only kept and extended when the peer
+    /// is a trusted proxy, and are otherwise replaced with values taken
+    /// from the client connection. Incoming values which are not visible
+    /// ASCII cannot be extended and are dropped as well.
+    pub(crate) fn apply(
+        &self,
+        req: &HttpRequest,
+        prefix: Option<&str>,
+        headers: &mut HeaderMap,
+    ) -> Result<(), Error> {
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        let trusted = peer.is_some_and(|ip| self.is_trusted(ip));
+        if !trusted || !self.mode.legacy() {
+            LEGACY_HEADERS.iter().for_each(|name| {
+                headers.remove(name);
+            });
+        }
+        if !trusted || !self.mode.standard() {
+            headers.remove(header::FORWARDED);
+        }
+        for name in LEGACY_HEADERS.iter().chain([&header::FORWARDED]) {
+            if headers.get_all(name).any(|value| value.to_str().is_err()) {
+                tracing::debug!("dropping malformed {name} header");
+                headers.remove(name);
+            }
+        }
+
+        let config = req.app_config();
+        let host = client_host(req);
+        let proto = match config.secure() {
+            true => "https",
+            false => "http",
+        };
+        let port = config.local_addr().port().to_string();
+
+        if self.mode.legacy() {
+            if let Some(ip) = peer {
+                update_forwarded(headers, header::X_FORWARDED_FOR, ip.to_string())?;
+            }
+            let values = [
+                (header::X_FORWARDED_HOST, host.as_str()),
+                (header::X_FORWARDED_PROTO, proto),
+                (X_FORWARDED_PORT, port.as_str()),
+            ];
+            for (name, value) in values {
+                if !headers.contains_key(&name) {
+                    headers.insert(name, HeaderValue::from_str(value)?);
+                }
+            }
+            if let Some(prefix) = prefix {
+                let prefix = match headers.get(&X_FORWARDED_PREFIX) {
+                    Some(outer) => format!("{}{prefix}", outer.to_str()?.trim_end_matches('/')),
+                    None => prefix.to_owned(),
+                };
+                headers.insert(X_FORWARDED_PREFIX, HeaderValue::from_str(&prefix)?);
+            }
+        }
+
+        if self.mode.standard() {
+            let node = match peer {
+                Some(IpAddr::V4(ip)) => ip.to_string(),
+                Some(IpAddr::V6(ip)) => format!("\"[{ip}]\""),
+                None => "unknown".to_owned(),
+            };
+            let element = format!("for={node};host=\"{host}\";proto={proto}");
+            let value = match headers.get(header::FORWARDED) {
+                Some(value) => format!("{}, {element}", value.to_str()?),
+                None => element,
+            };
+            headers.insert(header::FORWARDED, HeaderValue::from_str(&value)?);
+        }
+        Ok(())
+    }
+}
+
+/// Host requested by the client connection, ignoring forwarding headers
+pub(crate) fn client_host(req: &HttpRequest) -> String {
+    req.headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+        .unwrap_or_else(|| req.app_config().host())
+        .to_owned()
+}
+
+/// Parse a trusted proxy address or CIDR network
+pub(crate) fn parse_network(network: &str) -> Option<(IpAddr, u8)> {
+    let (ip, bits) = match network.split_once('/') {
+        Some((ip, bits)) => (ip.parse::<IpAddr>().ok()?, Some(bits.parse::<u8>().ok()?)),
+        None => (network.parse::<IpAddr>().ok()?, None),
+    };
+    let ip = ip.to_canonical();
+    let max = match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    let bits = bits.unwrap_or(max);
+    (bits <= max).then_some((ip, bits))
+}
@@ -1,7 +1,16 @@
 pub mod error;
 mod factory;
+mod forward;
+mod pages;
 pub mod proxy;
+mod reverse;
 mod service;
+mod stream;
+mod upgrade;
+mod upstream;
 
 pub use factory::RevProxy;
+pub use forward::Forwarding;
 pub use service::ProxyService;
+pub use stream::UpstreamUri;
+pub use upstream::{Balance, HealthCheck, Upstream};
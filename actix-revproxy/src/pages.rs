@@ -0,0 +1,57 @@
+//! Custom Error Pages
+use std::rc::Rc;
+
+use actix_web::{
+    HttpRequest, HttpResponse, error::Error as ActixError, http::StatusCode, web::Bytes,
+};
+
+/// Handler rendering a response for an error status
+pub(crate) type ErrorHandler = Rc<dyn Fn(StatusCode, &HttpRequest) -> HttpResponse>;
+
+/// Responses served in place of proxy failures and upstream errors
+#[derive(Clone, Default)]
+pub(crate) struct ErrorPages {
+    /// Static page bodies served for a specific status
+    pub(crate) pages: Vec<(StatusCode, Bytes)>,
+    /// Fallback handler for statuses without a static page
+    pub(crate) handler: Option<ErrorHandler>,
+    /// Replace upstream `5xx` responses
+    pub(crate) intercept: bool,
+}
+
+impl ErrorPages {
+    /// Render the configured page or handler response for a status
+    fn render(&self, req: &HttpRequest, status: StatusCode) -> Option<HttpResponse> {
+        if let Some((_, body)) = self.pages.iter().find(|(code, _)| *code == status) {
+            return Some(
+                HttpResponse::build(status)
+                    .content_type("text/html; charset=utf-8")
+                    .body(body.clone()),
+            );
+        }
+        self.handler.as_ref().map(|handler| handler(status, req))
+    }
+
+    /// Replace an upstream `5xx` response when interception is enabled
+    pub(crate) fn intercept(&self, req: &HttpRequest, res: HttpResponse) -> HttpResponse {
+        let status = res.status();
+        if !self.intercept || !status.is_server_error() {
+            return res;
+        }
+        tracing::debug!("intercepted upstream error response: {status}");
+        self.render(req, status).unwrap_or_else(|| {
+            let reason = status.canonical_reason().unwrap_or_default();
+            HttpResponse::build(status)
+                .content_type("text/plain; charset=utf-8")
+                .body(reason)
+        })
+    }
+
+    /// Convert a proxy failure into its configured error page, or
+    /// into an error response carrying the failure when none is configured
+    pub(crate) fn recover(&self, req: &HttpRequest, err: ActixError) -> HttpResponse {
+        let status = err.as_response_error().status_code();
+        self.render(req, status)
+            .unwrap_or_else(|| HttpResponse::from_error(err))
+    }
+}
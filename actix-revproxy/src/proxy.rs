@@ -1,10 +1,5 @@
 //! Actix-Web Proxy Utilities
-use std::{collections::HashMap, path::PathBuf};
-
-use actix_web::{
-    HttpMessage, HttpResponse,
-    web::{Bytes, Query},
-};
+use actix_web::{HttpMessage, HttpResponse, web::Bytes};
 use awc::{
     Client, ClientRequest,
     error::{HeaderValue, PayloadError},
@@ -79,30 +74,55 @@ where
     }
 }
 
-type QueryMap = Query<HashMap<String, String>>;
-
+/// Combine Proxy URI with Specified Target URI
+///
+/// See [`combine_path`] for details.
 #[inline]
-fn get_query(uri: &Uri) -> Result<QueryMap, UriError> {
-    Ok(QueryMap::from_query(uri.query().unwrap_or(""))?)
+pub fn combine_uri(proxy: &Uri, target: &Uri) -> Result<Uri, UriError> {
+    combine_path(proxy, target.path(), target.query())
 }
 
-/// Combine Proxy URI with Specified Target URI
-pub fn combine_uri(proxy: &Uri, target: &Uri) -> Result<Uri, UriError> {
+/// Combine Proxy URI with the specified target path and query
+///
+/// The target path is appended to the proxy path exactly as given, and the
+/// target query is appended after the proxy query without decoding,
+/// reordering or removing duplicate parameters.
+///
+/// # Examples
+///
+/// ```
+/// use actix_revproxy::proxy::combine_path;
+/// use awc::http::Uri;
+///
+/// let proxy = Uri::from_static("http://127.0.0.1:8080/api?key=1");
+/// let uri = combine_path(&proxy, "/users/a%2Fb", Some("page=1&page=2")).unwrap();
+/// assert_eq!(uri, "http://127.0.0.1:8080/api/users/a%2Fb?key=1&page=1&page=2");
+/// ```
+pub fn combine_path(proxy: &Uri, path: &str, query: Option<&str>) -> Result<Uri, UriError> {
     let authority = proxy.authority().ok_or(UriError::MissingAuthority)?;
-    let path = PathBuf::from(proxy.path())
-        .join(target.path())
-        .to_str()
-        .ok_or(UriError::InvalidUriPath)?
-        .to_owned();
+    let base = proxy.path().trim_end_matches('/');
+    let mut path_and_query = match path.is_empty() || path.starts_with('/') {
+        true => format!("{base}{path}"),
+        false => format!("{base}/{path}"),
+    };
+    if path_and_query.is_empty() {
+        path_and_query.push('/');
+    }
 
-    let mut query = get_query(proxy)?;
-    query.extend(get_query(target)?.into_inner());
-    let query = serde_urlencoded::to_string(query.into_inner())?;
+    let query: Vec<&str> = [proxy.query(), query]
+        .into_iter()
+        .flatten()
+        .filter(|query| !query.is_empty())
+        .collect();
+    if !query.is_empty() {
+        path_and_query.push('?');
+        path_and_query.push_str(&query.join("&"));
+    }
 
     Ok(Uri::builder()
         .scheme(proxy.scheme().cloned().unwrap_or(Scheme::HTTP))
         .authority(authority.clone())
-        .path_and_query(format!("{path}?{query}").trim_end_matches('?'))
+        .path_and_query(path_and_query)
         .build()?)
 }
 
@@ -0,0 +1,157 @@
+//! Reverse Rewriting of Upstream Response Headers
+use actix_web::HttpRequest;
+use awc::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+
+use crate::forward::client_host;
+
+const URL_HEADERS: [HeaderName; 3] = [
+    header::LOCATION,
+    header::CONTENT_LOCATION,
+    header::HeaderName::from_static("uri"),
+];
+
+/// Rules mapping upstream urls and cookie attributes back to the
+/// client-facing proxy, equivalent to Apache `ProxyPassReverse`,
+/// `ProxyPassReverseCookieDomain` and `ProxyPassReverseCookiePath`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReverseRules {
+    /// Local path prefix and the upstream url it replaces
+    pub(crate) urls: Vec<(String, String)>,
+    /// Local path prefix replacing the client-facing url, for upstreams
+    /// receiving the client host
+    pub(crate) client_path: Option<String>,
+    /// Upstream cookie domain and its public replacement
+    pub(crate) cookie_domains: Vec<(String, String)>,
+    /// Upstream cookie path prefix and its public replacement
+    pub(crate) cookie_paths: Vec<(String, String)>,
+}
+
+impl ReverseRules {
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+            && self.client_path.is_none()
+            && self.cookie_domains.is_empty()
+            && self.cookie_paths.is_empty()
+    }
+
+    /// Rewrite upstream response headers for the client request
+    pub(crate) fn apply(&self, req: &HttpRequest, headers: &mut HeaderMap) {
+        if self.is_empty() {
+            return;
+        }
+        // ignore client supplied forwarding headers when building the base url
+        let scheme = match req.app_config().secure() {
+            true => "https",
+            false => "http",
+        };
+        let base = format!("{scheme}://{}", client_host(req));
+
+        for name in URL_HEADERS {
+            let Some(value) = headers.get(&name).and_then(|v| v.to_str().ok()) else {
+                continue;
+            };
+            if let Some(value) = self.rewrite_url(value, &base)
+                && let Ok(value) = HeaderValue::from_str(&value)
+            {
+                headers.insert(name, value);
+            }
+        }
+
+        if let Some(value) = headers.get(header::REFRESH).and_then(|v| v.to_str().ok())
+            && let Some(value) = self.rewrite_refresh(value, &base)
+            && let Ok(value) = HeaderValue::from_str(&value)
+        {
+            headers.insert(header::REFRESH, value);
+        }
+
+        if self.cookie_domains.is_empty() && self.cookie_paths.is_empty() {
+            return;
+        }
+        let cookies: Vec<HeaderValue> = headers
+            .get_all(header::SET_COOKIE)
+            .map(|value| match value.to_str() {
+                Ok(cookie) => HeaderValue::from_str(&self.rewrite_cookie(cookie))
+                    .unwrap_or_else(|_| value.clone()),
+                Err(_) => value.clone(),
+            })
+            .collect();
+        headers.remove(header::SET_COOKIE);
+        for cookie in cookies {
+            headers.append(header::SET_COOKIE, cookie);
+        }
+    }
+
+    /// Replace a matching upstream url prefix with the client-facing url
+    fn rewrite_url(&self, value: &str, base: &str) -> Option<String> {
+        let urls = self.urls.iter().map(|(path, url)| (path, url.as_str()));
+        let client = self.client_path.iter().map(|path| (path, base));
+        urls.chain(client).find_map(|(path, url)| {
+            let rest = strip_path_prefix(value, url)?;
+            let path = format!("{}{rest}", path.trim_end_matches('/'));
+            match path.is_empty() {
+                true => Some(format!("{base}/")),
+                false => Some(format!("{base}{path}")),
+            }
+        })
+    }
+
+    /// Rewrite the url component of a `Refresh` header
+    fn rewrite_refresh(&self, value: &str, base: &str) -> Option<String> {
+        let start = value.to_ascii_lowercase().find("url=")? + 4;
+        let (delay, url) = value.split_at(start);
+        let quote = match url.starts_with(['"', '\'']) {
+            true => &url[..1],
+            false => "",
+        };
+        let url = &url[quote.len()..];
+        Some(format!("{delay}{quote}{}", self.rewrite_url(url, base)?))
+    }
+
+    /// Rewrite the `Domain` and `Path` attributes of a `Set-Cookie` value
+    fn rewrite_cookie(&self, cookie: &str) -> String {
+        let mut parts = cookie.split(';');
+        let mut rewritten = vec![parts.next().unwrap_or_default().to_owned()];
+        for attr in parts {
+            let (key, value) = attr.split_once('=').unwrap_or((attr, ""));
+            let key = key.trim();
+            let value = value.trim();
+            if key.eq_ignore_ascii_case("domain") {
+                let domain = value.trim_start_matches('.');
+                let found = self
+                    .cookie_domains
+                    .iter()
+                    .find(|(from, _)| from.trim_start_matches('.').eq_ignore_ascii_case(domain));
+                match found {
+                    Some((_, to)) if to.is_empty() => continue,
+                    Some((_, to)) => rewritten.push(format!(" {key}={to}")),
+                    None => rewritten.push(attr.to_owned()),
+                }
+                continue;
+            }
+            if key.eq_ignore_ascii_case("path") {
+                let found = self.cookie_paths.iter().find_map(|(from, to)| {
+                    let rest = strip_path_prefix(value, from)?;
+                    let path = format!("{}{rest}", to.trim_end_matches('/'));
+                    Some(match path.is_empty() {
+                        true => "/".to_owned(),
+                        false => path,
+                    })
+                });
+                match found {
+                    Some(path) => rewritten.push(format!(" {key}={path}")),
+                    None => rewritten.push(attr.to_owned()),
+                }
+                continue;
+            }
+            rewritten.push(attr.to_owned());
+        }
+        rewritten.join(";")
+    }
+}
+
+/// Strip a url or path prefix only when it ends on a path boundary
+fn strip_path_prefix<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = value.strip_prefix(prefix.trim_end_matches('/'))?;
+    (rest.is_empty() || rest.starts_with(['/', '?', '#'])).then_some(rest)
+}
@@ -1,19 +1,22 @@
 use std::{ops::Deref, rc::Rc};
 
+use actix_http::ws;
 use actix_web::{
-    HttpRequest,
+    HttpRequest, HttpResponse,
     body::BoxBody,
-    dev::{self, Service, ServiceRequest, ServiceResponse},
+    dev::{self, Payload, Service, ServiceRequest, ServiceResponse},
     error::Error as ActixError,
 };
-use awc::{
-    Client, ClientRequest,
-    http::{Uri, header},
-};
+use awc::{Client, ClientRequest, error::SendRequestError, http::header};
 use futures_core::future::LocalBoxFuture;
 
 use crate::error::Error;
+use crate::forward::{ForwardPolicy, client_host};
+use crate::pages::ErrorPages;
 use crate::proxy::*;
+use crate::reverse::ReverseRules;
+use crate::upgrade::{Limits, is_websocket, tunnel};
+use crate::upstream::{Lease, Pool};
 
 pub type HeaderVec = Vec<(header::HeaderName, header::HeaderValue)>;
 
@@ -24,23 +27,25 @@ pub struct ProxyService(pub(crate) Rc<ProxyServiceInner>);
 impl ProxyService {
     /// Convert [`actix_web::HttpRequest`] into [`awc::ClientRequest`]
     #[inline]
-    fn prepare_request(&self, req: &HttpRequest) -> Result<ClientRequest, Error> {
-        let info = req.connection_info().clone();
-        let uri = combine_uri(&self.resolve, req.uri())?;
-
-        let mut request = req.client_req(&self.client, uri)?.no_decompress();
-        if !self.change_host {
-            request = request.insert_header((header::HOST, info.host()))
-        }
-
-        if let Some(addr) = req.peer_addr() {
-            let ip = addr.ip().to_string();
-            let proto = request.get_uri().scheme_str().unwrap_or("http").to_owned();
-            request = request
-                .insert_header((header::X_FORWARDED_HOST, info.host()))
-                .insert_header((header::X_FORWARDED_PROTO, proto));
-            update_forwarded(request.headers_mut(), header::X_FORWARDED_FOR, ip)?;
+    fn prepare_request(&self, req: &HttpRequest, lease: &Lease) -> Result<ClientRequest, Error> {
+        let raw = req.uri().path();
+        let (path, prefix) = match self.strip_prefix {
+            true => {
+                let path = strip_matched_prefix(req);
+                let prefix = raw[..raw.len() - path.len()].trim_end_matches('/');
+                (path, Some(prefix).filter(|prefix| !prefix.is_empty()))
+            }
+            false => (raw, None),
+        };
+        let uri = combine_path(lease.uri().uri(), path, req.uri().query())?;
+
+        let client = lease.client().unwrap_or(&self.client);
+        let mut request = req.client_req(client, uri)?.no_decompress();
+        // unix socket upstreams have no hostname of their own
+        if !self.change_host || lease.uri().socket().is_some() {
+            request = request.insert_header((header::HOST, client_host(req)))
         }
+        self.forwarding.apply(req, prefix, request.headers_mut())?;
 
         for (name, value) in self.header_up.clone() {
             match value.is_empty() {
@@ -50,6 +55,127 @@ impl ProxyService {
         }
         Ok(request)
     }
+
+    /// Apply configured downstream headers to the response
+    fn apply_header_down(&self, res: &mut HttpResponse) {
+        for (name, value) in self.header_down.clone() {
+            match value.is_empty() {
+                true => res.headers_mut().remove(name),
+                false => res.headers_mut().insert(name, value),
+            };
+        }
+    }
+
+    /// Send the request to an upstream, retrying idempotent requests on
+    /// other upstreams when the connection fails
+    async fn forward(
+        &self,
+        req: &HttpRequest,
+        mut payload: Payload,
+    ) -> Result<HttpResponse, ActixError> {
+        let addr = req
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "<unknown>".to_owned());
+        let retryable = req.method().is_idempotent() && !has_body(req);
+        let mut tried = Vec::new();
+        let (lease, response) = loop {
+            let lease = self
+                .pool
+                .select(req, &tried)
+                .inspect_err(|err| tracing::error!("request failed: {err}"))?;
+            let request = self
+                .prepare_request(req, &lease)
+                .inspect_err(|err| tracing::error!("invalid request: {err:?}"))?;
+
+            tracing::debug!("{addr} {:?} {:?}", req.method(), request.get_uri());
+            tracing::trace!(?addr, ?request);
+            let result = match retryable {
+                true => request.send().await,
+                false => request.send_stream(payload.take()).await,
+            };
+            match result {
+                Ok(response) => {
+                    match response.status().is_server_error() {
+                        true => lease.failure(),
+                        false => lease.success(),
+                    }
+                    break (lease, response);
+                }
+                Err(err) => {
+                    lease.failure();
+                    let connect = matches!(err, SendRequestError::Connect(_));
+                    if !(retryable && connect && tried.len() < self.max_retries) {
+                        tracing::error!("request failed: {err:?}");
+                        return Err(Error::FailedRequest(err).into());
+                    }
+                    tracing::warn!("upstream {} unreachable, retrying: {err}", lease.uri());
+                    tried.push(lease.index());
+                }
+            }
+        };
+        tracing::trace!(?addr, ?response);
+
+        let http_res = response
+            .server_response()
+            .inspect_err(|err| tracing::error!("invalid response: {err:?}"))?;
+        Ok(lease.attach(http_res))
+    }
+
+    /// Complete WebSocket handshake with upstream and tunnel the connection
+    async fn upgrade(
+        &self,
+        req: &HttpRequest,
+        payload: Payload,
+    ) -> Result<HttpResponse, ActixError> {
+        tracing::debug!("{:?} upgrade {:?}", req.peer_addr(), req.uri());
+        ws::verify_handshake(req.head())?;
+        let key = req
+            .headers()
+            .get(header::SEC_WEBSOCKET_KEY)
+            .map(|key| ws::hash_key(key.as_bytes()))
+            .ok_or(ws::HandshakeError::BadWebsocketKey)?;
+
+        let lease = self.pool.select(req, &[])?;
+        let request = self
+            .prepare_request(req, &lease)
+            .inspect_err(|err| tracing::error!("invalid request: {err:?}"))?;
+        let client = lease.client().unwrap_or(&self.client);
+        let mut connect = client.ws(request.get_uri());
+        for (name, value) in request.headers() {
+            if name != header::SEC_WEBSOCKET_KEY && name != header::SEC_WEBSOCKET_VERSION {
+                connect = connect.header(name.clone(), value.clone());
+            }
+        }
+
+        let (response, framed) = connect
+            .connect()
+            .await
+            .map_err(Error::FailedUpgrade)
+            .inspect_err(|err| {
+                lease.failure();
+                tracing::error!("upgrade failed: {err:?}")
+            })?;
+        lease.success();
+        tracing::trace!(?response);
+
+        let mut headers = response.headers().clone();
+        remove_connection_headers(&mut headers)?;
+        remove_hop_headers(&mut headers);
+        headers.remove(header::SEC_WEBSOCKET_ACCEPT);
+
+        let mut builder = HttpResponse::SwitchingProtocols();
+        builder
+            .upgrade("websocket")
+            .insert_header((header::SEC_WEBSOCKET_ACCEPT, &key[..]));
+        for header in headers.iter() {
+            builder.append_header(header);
+        }
+
+        let parts = framed.into_parts();
+        let body = tunnel(parts.io, parts.read_buf.freeze(), payload, self.websocket);
+        Ok(lease.attach(builder.streaming(body)))
+    }
 }
 
 impl Deref for ProxyService {
@@ -62,10 +188,16 @@ impl Deref for ProxyService {
 
 pub struct ProxyServiceInner {
     pub(crate) client: Rc<Client>,
-    pub(crate) resolve: Uri,
+    pub(crate) pool: Rc<Pool>,
+    pub(crate) max_retries: usize,
     pub(crate) change_host: bool,
+    pub(crate) strip_prefix: bool,
+    pub(crate) forwarding: ForwardPolicy,
     pub(crate) header_up: HeaderVec,
     pub(crate) header_down: HeaderVec,
+    pub(crate) reverse: ReverseRules,
+    pub(crate) websocket: Limits,
+    pub(crate) errors: ErrorPages,
 }
 
 impl Service<ServiceRequest> for ProxyService {
@@ -79,34 +211,45 @@ impl Service<ServiceRequest> for ProxyService {
         let this = self.clone();
         Box::pin(async move {
             let (http_req, payload) = req.into_parts();
-
-            let addr = http_req
-                .peer_addr()
-                .map(|addr| addr.to_string())
-                .unwrap_or_else(|| "<unknown>".to_owned());
-            let request = this
-                .prepare_request(&http_req)
-                .inspect_err(|err| tracing::error!("invalid request: {err:?}"))?;
-
-            tracing::debug!("{addr} {:?} {:?}", http_req.method(), request.get_uri());
-            tracing::trace!(?addr, ?request);
-            let response = request
-                .send_stream(payload)
-                .await
-                .map_err(Error::FailedRequest)
-                .inspect_err(|err| tracing::error!("request failed: {err:?}"))?;
-            tracing::trace!(?addr, ?response);
-
-            let mut http_res = response
-                .server_response()
-                .inspect_err(|err| tracing::error!("invalid response: {err:?}"))?;
-            for (name, value) in this.header_down.clone() {
-                match value.is_empty() {
-                    true => http_res.headers_mut().remove(name),
-                    false => http_res.headers_mut().insert(name, value),
-                };
-            }
+            let result = match is_websocket(&http_req) {
+                true => this.upgrade(&http_req, payload).await,
+                false => this.forward(&http_req, payload).await,
+            };
+            let mut http_res = match result {
+                Ok(http_res) => this.errors.intercept(&http_req, http_res),
+                Err(err) => this.errors.recover(&http_req, err),
+            };
+            this.reverse.apply(&http_req, http_res.headers_mut());
+            this.apply_header_down(&mut http_res);
             Ok(ServiceResponse::new(http_req, http_res))
         })
     }
 }
+
+/// Slice the raw request path after the prefix matched by the router.
+///
+/// The matched path is percent-decoded by the router, but never decodes
+/// `/`, so the prefix is located in the raw path by its slashes.
+fn strip_matched_prefix(req: &HttpRequest) -> &str {
+    let raw = req.uri().path();
+    let matched = req.match_info().as_str();
+    let rest = req.match_info().unprocessed();
+    let processed = &matched[..matched.len() - rest.len()];
+
+    let slashes = processed.matches('/').count();
+    let mut positions = raw.match_indices('/').map(|(index, _)| index);
+    let offset = match processed.ends_with('/') {
+        true => positions.nth(slashes - 1).map(|index| index + 1),
+        false => positions.nth(slashes),
+    };
+    &raw[offset.unwrap_or(raw.len())..]
+}
+
+/// Check if the request is sending a body to the upstream
+fn has_body(req: &HttpRequest) -> bool {
+    let headers = req.headers();
+    headers.contains_key(header::TRANSFER_ENCODING)
+        || headers
+            .get(header::CONTENT_LENGTH)
+            .is_some_and(|value| value.as_bytes() != b"0")
+}
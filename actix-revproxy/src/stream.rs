@@ -0,0 +1,169 @@
+//! Unix Domain Socket Upstream Connections
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use actix_rt::net::UnixStream;
+use actix_service::Service;
+use actix_tls::connect::{ConnectError, ConnectInfo, Connection};
+use awc::{
+    Client, Connector,
+    error::HttpError,
+    http::{Uri, uri::Parts},
+};
+use futures_core::future::LocalBoxFuture;
+
+use crate::error::UriError;
+
+/// Resolution uri of an upstream, accepting `http://`, `https://` and
+/// `unix://` uris.
+///
+/// Unix socket upstreams are requested through a placeholder `http://` uri
+/// by a dedicated client connecting to the socket, and are displayed as
+/// `unix:/path/to.sock`.
+#[derive(Clone, Debug)]
+pub struct UpstreamUri {
+    uri: Uri,
+    socket: Option<PathBuf>,
+}
+
+impl UpstreamUri {
+    /// Uri requests to the upstream are sent to
+    #[inline]
+    pub(crate) fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    /// Path of the unix socket the upstream listens on
+    #[inline]
+    pub(crate) fn socket(&self) -> Option<&Path> {
+        self.socket.as_deref()
+    }
+
+    fn unix(path: &str) -> Result<Self, UriError> {
+        if path.is_empty() {
+            return Err(UriError::MissingSocketPath);
+        }
+        Ok(Self {
+            uri: Uri::from_static("http://localhost"),
+            socket: Some(PathBuf::from(path)),
+        })
+    }
+}
+
+impl fmt::Display for UpstreamUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.socket {
+            Some(path) => write!(f, "unix:{}", path.display()),
+            None => self.uri.fmt(f),
+        }
+    }
+}
+
+impl From<Uri> for UpstreamUri {
+    #[inline]
+    fn from(uri: Uri) -> Self {
+        Self { uri, socket: None }
+    }
+}
+
+impl From<&Uri> for UpstreamUri {
+    #[inline]
+    fn from(uri: &Uri) -> Self {
+        uri.clone().into()
+    }
+}
+
+impl TryFrom<Parts> for UpstreamUri {
+    type Error = UriError;
+
+    #[inline]
+    fn try_from(value: Parts) -> Result<Self, Self::Error> {
+        Ok(Uri::try_from(value).map_err(HttpError::from)?.into())
+    }
+}
+
+impl TryFrom<&str> for UpstreamUri {
+    type Error = UriError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.split_once("://") {
+            Some((scheme, path)) if scheme.eq_ignore_ascii_case("unix") => Self::unix(path),
+            _ => Ok(Uri::try_from(value)?.into()),
+        }
+    }
+}
+
+impl TryFrom<String> for UpstreamUri {
+    type Error = UriError;
+
+    #[inline]
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.as_str().try_into()
+    }
+}
+
+impl TryFrom<&String> for UpstreamUri {
+    type Error = UriError;
+
+    #[inline]
+    fn try_from(value: &String) -> Result<Self, Self::Error> {
+        value.as_str().try_into()
+    }
+}
+
+impl TryFrom<&[u8]> for UpstreamUri {
+    type Error = UriError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        match std::str::from_utf8(value) {
+            Ok(value) => value.try_into(),
+            Err(_) => Ok(Uri::try_from(value)?.into()),
+        }
+    }
+}
+
+impl TryFrom<Vec<u8>> for UpstreamUri {
+    type Error = UriError;
+
+    #[inline]
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        value.as_slice().try_into()
+    }
+}
+
+/// Build the client sending requests to a unix socket upstream.
+///
+/// Every unix socket upstream is requested by its own client, so custom
+/// clients passed to [`RevProxy::with_client`](crate::RevProxy::with_client)
+/// are only used for tcp upstreams.
+pub(crate) fn unix_client(path: &Path) -> Client {
+    let connector = UnixConnector(Rc::from(path));
+    Client::builder()
+        .connector(Connector::new().connector(connector))
+        .finish()
+}
+
+/// [`awc`] connector service opening connections to a single unix socket
+/// regardless of the requested host.
+#[derive(Clone)]
+struct UnixConnector(Rc<Path>);
+
+impl Service<ConnectInfo<Uri>> for UnixConnector {
+    type Response = Connection<Uri, UnixStream>;
+    type Error = ConnectError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_service::always_ready!();
+
+    fn call(&self, req: ConnectInfo<Uri>) -> Self::Future {
+        let path = Rc::clone(&self.0);
+        let uri = req.request().clone();
+        Box::pin(async move {
+            let io = UnixStream::connect(&path).await.map_err(ConnectError::Io)?;
+            Ok(Connection::new(uri, io))
+        })
+    }
+}
@@ -0,0 +1,341 @@
+//! WebSocket Upgrade Tunneling
+use std::{
+    cell::{Cell, RefCell},
+    future::poll_fn,
+    io,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker, ready},
+    time::Duration,
+};
+
+use actix_codec::{AsyncRead, AsyncWrite, ReadBuf};
+use actix_web::{
+    HttpRequest,
+    dev::Payload,
+    rt::{
+        task::JoinHandle,
+        time::{Instant, Sleep, sleep},
+    },
+    web::Bytes,
+};
+use awc::http::header;
+use futures_core::Stream;
+
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Close frame with status 1009 (message too big) sent to the client
+const CLOSE_TOO_BIG: [u8; 4] = [0x88, 0x02, 0x03, 0xF1];
+
+/// Limits applied to proxied WebSocket connections
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Limits {
+    pub(crate) idle_timeout: Duration,
+    pub(crate) max_message_size: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(60),
+            max_message_size: None,
+        }
+    }
+}
+
+/// Check if request is asking to upgrade to a WebSocket connection
+pub(crate) fn is_websocket(req: &HttpRequest) -> bool {
+    req.head().upgrade()
+        && req
+            .headers()
+            .get(header::UPGRADE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.to_ascii_lowercase().contains("websocket"))
+}
+
+/// Tunnel bytes between the client and an upgraded upstream connection.
+///
+/// Client bytes are forwarded to the upstream by a spawned task, while
+/// upstream bytes are returned as a stream to use as the response body.
+/// The spawned task is aborted once the returned stream closes or is dropped.
+pub(crate) fn tunnel<Io>(
+    io: Io,
+    read_buf: Bytes,
+    payload: Payload,
+    limits: Limits,
+) -> Downstream<Io>
+where
+    Io: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    let tunnel = Rc::new(Tunnel {
+        io: RefCell::new(io),
+        closed: Cell::new(false),
+        exceeded: Cell::new(false),
+        active: Cell::new(Instant::now()),
+        waker: Cell::new(None),
+    });
+    let limit = FrameLimit::new("client", limits.max_message_size);
+    let pump = actix_web::rt::spawn(upstream(tunnel.clone(), payload, limit));
+    Downstream {
+        tunnel,
+        pump,
+        buffered: Some(read_buf),
+        limit: FrameLimit::new("upstream", limits.max_message_size),
+        idle_timeout: limits.idle_timeout,
+        timer: Box::pin(sleep(limits.idle_timeout)),
+    }
+}
+
+/// Upstream connection shared by both directions of the tunnel
+struct Tunnel<Io> {
+    io: RefCell<Io>,
+    closed: Cell<bool>,
+    exceeded: Cell<bool>,
+    active: Cell<Instant>,
+    waker: Cell<Option<Waker>>,
+}
+
+impl<Io> Tunnel<Io>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Record activity on the tunnel to reset the idle timeout
+    #[inline]
+    fn touch(&self) {
+        self.active.set(Instant::now());
+    }
+
+    /// Mark tunnel as closed and wake the downstream reader
+    fn close(&self) {
+        self.closed.set(true);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn poll_read(&self, cx: &mut Context<'_>) -> Poll<io::Result<Bytes>> {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        let mut read = ReadBuf::new(&mut buf);
+        ready!(Pin::new(&mut *self.io.borrow_mut()).poll_read(cx, &mut read))?;
+        Poll::Ready(Ok(Bytes::copy_from_slice(read.filled())))
+    }
+
+    async fn write_all(&self, mut data: &[u8]) -> io::Result<()> {
+        let io = &self.io;
+        while !data.is_empty() {
+            let n = poll_fn(|cx| Pin::new(&mut *io.borrow_mut()).poll_write(cx, data)).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            data = &data[n..];
+        }
+        poll_fn(|cx| Pin::new(&mut *io.borrow_mut()).poll_flush(cx)).await
+    }
+
+    async fn shutdown(&self) {
+        let io = &self.io;
+        let _ = poll_fn(|cx| Pin::new(&mut *io.borrow_mut()).poll_shutdown(cx)).await;
+    }
+}
+
+/// Forward client bytes to the upstream connection until either side closes
+async fn upstream<Io>(tunnel: Rc<Tunnel<Io>>, mut payload: Payload, mut limit: FrameLimit)
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(chunk) = poll_fn(|cx| Pin::new(&mut payload).poll_next(cx)).await {
+        if tunnel.closed.get() {
+            break;
+        }
+        let data = match chunk {
+            Ok(data) => data,
+            Err(err) => {
+                tracing::debug!("websocket client read failed: {err}");
+                break;
+            }
+        };
+        if !limit.check(&data) {
+            tunnel.exceeded.set(true);
+            tunnel.close();
+            break;
+        }
+        tunnel.touch();
+        if let Err(err) = tunnel.write_all(&data).await {
+            tracing::debug!("websocket upstream write failed: {err}");
+            tunnel.close();
+            break;
+        }
+    }
+    tunnel.shutdown().await;
+}
+
+/// Upstream bytes of the tunnel streamed back to the client
+pub(crate) struct Downstream<Io> {
+    tunnel: Rc<Tunnel<Io>>,
+    pump: JoinHandle<()>,
+    buffered: Option<Bytes>,
+    limit: FrameLimit,
+    idle_timeout: Duration,
+    timer: Pin<Box<Sleep>>,
+}
+
+impl<Io> Downstream<Io>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Close both directions of the tunnel, releasing the upstream connection
+    fn close(&self) {
+        self.tunnel.close();
+        self.pump.abort();
+    }
+
+    /// Close the tunnel, telling the client why if a message exceeded the
+    /// size limit and no upstream frame was left partially forwarded
+    fn close_exceeded(&self, boundary: bool) -> Option<io::Result<Bytes>> {
+        self.close();
+        boundary.then(|| Ok(Bytes::from_static(&CLOSE_TOO_BIG)))
+    }
+
+    /// Close the tunnel once no bytes were sent in either direction
+    /// for the configured idle timeout
+    fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        self.tunnel.waker.set(Some(cx.waker().clone()));
+        let deadline = self.tunnel.active.get() + self.idle_timeout;
+        if self.timer.deadline() != deadline {
+            self.timer.as_mut().reset(deadline);
+        }
+        ready!(self.timer.as_mut().poll(cx));
+        tracing::debug!("websocket connection idle timeout");
+        self.close();
+        Poll::Ready(None)
+    }
+}
+
+impl<Io> Stream for Downstream<Io>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.tunnel.closed.get() {
+            let boundary = this.tunnel.exceeded.take() && this.limit.at_boundary();
+            return Poll::Ready(this.close_exceeded(boundary));
+        }
+        let data = match this.buffered.take().filter(|data| !data.is_empty()) {
+            Some(data) => data,
+            None => match this.tunnel.poll_read(cx) {
+                Poll::Ready(Ok(data)) if data.is_empty() => {
+                    this.close();
+                    return Poll::Ready(None);
+                }
+                Poll::Ready(Ok(data)) => data,
+                Poll::Ready(Err(err)) => {
+                    this.close();
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Pending => return this.poll_idle(cx),
+            },
+        };
+        let boundary = this.limit.at_boundary();
+        if !this.limit.check(&data) {
+            return Poll::Ready(this.close_exceeded(boundary));
+        }
+        this.tunnel.touch();
+        Poll::Ready(Some(Ok(data)))
+    }
+}
+
+impl<Io> Drop for Downstream<Io> {
+    fn drop(&mut self) {
+        self.pump.abort();
+    }
+}
+
+/// Tracks WebSocket frame boundaries within one direction of the tunnel
+/// to enforce the maximum message size across fragmented messages
+struct FrameLimit {
+    side: &'static str,
+    max: Option<u64>,
+    header: Vec<u8>,
+    remaining: u64,
+    message: u64,
+}
+
+impl FrameLimit {
+    fn new(side: &'static str, max: Option<usize>) -> Self {
+        Self {
+            side,
+            max: max.map(|max| max as u64),
+            header: Vec::with_capacity(14),
+            remaining: 0,
+            message: 0,
+        }
+    }
+
+    /// Check if all consumed bytes ended on a frame boundary
+    #[inline]
+    fn at_boundary(&self) -> bool {
+        self.remaining == 0 && self.header.is_empty()
+    }
+
+    /// Consume the next chunk of bytes, returning false once a message
+    /// exceeds the limit
+    fn check(&mut self, mut data: &[u8]) -> bool {
+        let Some(max) = self.max else {
+            return true;
+        };
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let n = self.remaining.min(data.len() as u64);
+                self.remaining -= n;
+                data = &data[n as usize..];
+                continue;
+            }
+            self.header.push(data[0]);
+            data = &data[1..];
+            let Some((opcode, len)) = parse_frame_header(&self.header) else {
+                continue;
+            };
+            self.header.clear();
+            self.remaining = len;
+            // control frames may be interleaved with fragmented messages
+            if opcode & 0x08 != 0 {
+                continue;
+            }
+            self.message = match opcode {
+                0 => self.message.saturating_add(len),
+                _ => len,
+            };
+            if self.message > max {
+                tracing::warn!(
+                    "websocket {} message of {} bytes exceeded size limit of {max} bytes",
+                    self.side,
+                    self.message
+                );
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Parse a complete WebSocket frame header into its opcode and payload length
+fn parse_frame_header(header: &[u8]) -> Option<(u8, u64)> {
+    if header.len() < 2 {
+        return None;
+    }
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+    let (len, offset) = match header[1] & 0x7F {
+        126 => (
+            u16::from_be_bytes(header.get(2..4)?.try_into().ok()?) as u64,
+            4,
+        ),
+        127 => (u64::from_be_bytes(header.get(2..10)?.try_into().ok()?), 10),
+        len => (len as u64, 2),
+    };
+    let size = if masked { offset + 4 } else { offset };
+    (header.len() >= size).then_some((opcode, len))
+}
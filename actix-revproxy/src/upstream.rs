@@ -0,0 +1,531 @@
+//! Upstream Pool Balancing and Health Checks
+use std::{
+    cell::Cell,
+    collections::hash_map::DefaultHasher,
+    error::Error as StdError,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    pin::Pin,
+    rc::{Rc, Weak},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    body::{BodySize, BoxBody, MessageBody},
+    rt::time::{Instant, sleep},
+    web::Bytes,
+};
+use awc::{
+    Client,
+    http::{Uri, header::HeaderName},
+};
+
+use crate::error::Error;
+use crate::proxy::combine_path;
+use crate::stream::{UpstreamUri, unix_client};
+
+/// Virtual nodes placed on the hash ring per unit of weight
+const RING_POINTS: u32 = 100;
+
+/// Upstream server within a [`RevProxy`](crate::RevProxy) pool
+///
+/// Like the rest of the pool state, connection counts, health and
+/// ejections are tracked separately by every worker.
+///
+/// # Examples
+///
+/// ```
+/// use actix_revproxy::{RevProxy, Upstream};
+///
+/// RevProxy::pool("/", [
+///     Upstream::new("http://10.0.0.1:8080").weight(3),
+///     Upstream::new("http://10.0.0.2:8080").max_connections(100),
+/// ]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    uri: UpstreamUri,
+    weight: u32,
+    max_connections: Option<usize>,
+}
+
+impl Upstream {
+    /// Creates new `Upstream` for the specified resolution uri
+    ///
+    /// Unix socket upstreams are supported using `unix:///run/app.sock`
+    /// style uris.
+    pub fn new<U: TryInto<UpstreamUri>>(uri: U) -> Self
+    where
+        U::Error: Debug,
+    {
+        Self {
+            uri: uri.try_into().expect("invalid resolution uri"),
+            weight: 1,
+            max_connections: None,
+        }
+    }
+
+    /// Resolution uri of the upstream
+    #[inline]
+    pub(crate) fn uri(&self) -> &UpstreamUri {
+        &self.uri
+    }
+
+    /// Configure the relative share of requests sent to this upstream
+    ///
+    /// Default is 1.
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = weight.max(1);
+        self
+    }
+
+    /// Configure the maximum number of concurrent requests sent to this
+    /// upstream. Once reached, the upstream is skipped by the balancer.
+    ///
+    /// **IMPORTANT:** The limit applies to every worker independently,
+    /// so up to `workers * max` requests may reach the upstream at once.
+    ///
+    /// Default is unlimited.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+}
+
+/// Load balancing strategy between upstreams of a pool
+#[derive(Clone, Debug, Default)]
+pub enum Balance {
+    /// Weighted round-robin
+    #[default]
+    RoundRobin,
+    /// Upstream with the fewest active requests relative to its weight
+    LeastConnections,
+    /// Better of two randomly chosen upstreams by active requests
+    RandomTwoChoices,
+    /// Consistent hash on the value of a request header
+    ///
+    /// Requests without the header fall back to round-robin.
+    Header(HeaderName),
+    /// Consistent hash on the value of a request cookie
+    ///
+    /// Requests without the cookie fall back to round-robin.
+    Cookie(String),
+}
+
+/// Active health check periodically sent to every upstream of a pool
+///
+/// Upstreams are marked unhealthy when the check fails to return a
+/// successful or redirect response, and healthy again once it passes.
+/// The check path is appended to the path of each upstream uri.
+///
+/// **IMPORTANT:** Checks run independently on every worker, each sending
+/// its own requests and tracking its own upstream health.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use actix_revproxy::HealthCheck;
+///
+/// HealthCheck::new("/healthz")
+///     .interval(Duration::from_secs(5))
+///     .timeout(Duration::from_secs(1));
+/// ```
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    path: String,
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl HealthCheck {
+    /// Creates new `HealthCheck` requesting the specified path
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Configure the delay between health checks
+    ///
+    /// Default is 10 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Configure how long to wait for a health check response
+    ///
+    /// Default is 5 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Passive ejection of upstreams after consecutive failed requests
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Ejection {
+    pub(crate) max_failures: u32,
+    pub(crate) duration: Duration,
+}
+
+/// Per-worker state of an upstream
+struct UpstreamState {
+    uri: UpstreamUri,
+    /// Client connecting to the socket of unix socket upstreams
+    client: Option<Client>,
+    weight: u32,
+    max_connections: Option<usize>,
+    active: Cell<usize>,
+    healthy: Cell<bool>,
+    failures: Cell<u32>,
+    ejected: Cell<Option<Instant>>,
+    current: Cell<i64>,
+}
+
+impl UpstreamState {
+    /// Check if upstream may receive new requests
+    fn available(&self) -> bool {
+        if let Some(until) = self.ejected.get() {
+            if Instant::now() < until {
+                return false;
+            }
+            self.ejected.set(None);
+        }
+        self.healthy.get()
+            && self
+                .max_connections
+                .is_none_or(|max| self.active.get() < max)
+    }
+}
+
+/// Per-worker pool of upstreams
+pub(crate) struct Pool {
+    upstreams: Vec<UpstreamState>,
+    balance: Balance,
+    ejection: Option<Ejection>,
+    ring: Vec<(u64, usize)>,
+}
+
+impl Pool {
+    pub(crate) fn new(
+        upstreams: &[Upstream],
+        balance: Balance,
+        ejection: Option<Ejection>,
+    ) -> Self {
+        let mut ring: Vec<(u64, usize)> = Vec::new();
+        if matches!(balance, Balance::Header(_) | Balance::Cookie(_)) {
+            for (index, upstream) in upstreams.iter().enumerate() {
+                let points = upstream.weight.saturating_mul(RING_POINTS);
+                ring.extend((0..points).map(|n| (hash(&(upstream.uri.to_string(), n)), index)));
+            }
+            ring.sort_unstable();
+        }
+        Self {
+            upstreams: upstreams
+                .iter()
+                .map(|upstream| UpstreamState {
+                    uri: upstream.uri.clone(),
+                    client: upstream.uri.socket().map(unix_client),
+                    weight: upstream.weight,
+                    max_connections: upstream.max_connections,
+                    active: Cell::new(0),
+                    healthy: Cell::new(true),
+                    failures: Cell::new(0),
+                    ejected: Cell::new(None),
+                    current: Cell::new(0),
+                })
+                .collect(),
+            balance,
+            ejection,
+            ring,
+        }
+    }
+
+    /// Number of upstreams in the pool
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.upstreams.len()
+    }
+
+    /// Select an available upstream for the request, skipping any
+    /// upstreams which were already tried.
+    pub(crate) fn select(
+        self: &Rc<Self>,
+        req: &HttpRequest,
+        tried: &[usize],
+    ) -> Result<Lease, Error> {
+        let candidates: Vec<usize> = (0..self.upstreams.len())
+            .filter(|index| !tried.contains(index) && self.upstreams[*index].available())
+            .collect();
+        if candidates.is_empty() {
+            return Err(Error::NoHealthyUpstream);
+        }
+        let index = match &self.balance {
+            Balance::RoundRobin => self.round_robin(&candidates),
+            Balance::LeastConnections => self.least_connections(&candidates),
+            Balance::RandomTwoChoices => self.random_two_choices(&candidates),
+            Balance::Header(name) => match req.headers().get(name) {
+                Some(value) => self.consistent_hash(value.as_bytes(), &candidates),
+                None => self.round_robin(&candidates),
+            },
+            Balance::Cookie(name) => match req.cookie(name) {
+                Some(cookie) => self.consistent_hash(cookie.value().as_bytes(), &candidates),
+                None => self.round_robin(&candidates),
+            },
+        };
+        Ok(Lease::new(self.clone(), index))
+    }
+
+    /// Smooth weighted round-robin between candidates
+    fn round_robin(&self, candidates: &[usize]) -> usize {
+        let mut total = 0i64;
+        let mut best = candidates[0];
+        for index in candidates.iter().copied() {
+            let upstream = &self.upstreams[index];
+            upstream
+                .current
+                .set(upstream.current.get() + upstream.weight as i64);
+            total += upstream.weight as i64;
+            if upstream.current.get() > self.upstreams[best].current.get() {
+                best = index;
+            }
+        }
+        let upstream = &self.upstreams[best];
+        upstream.current.set(upstream.current.get() - total);
+        best
+    }
+
+    /// Compare active requests of two upstreams relative to their weight
+    #[inline]
+    fn load(&self, index: usize) -> (u64, u64) {
+        let upstream = &self.upstreams[index];
+        (upstream.active.get() as u64, upstream.weight as u64)
+    }
+
+    /// Weighted round-robin between candidates with the fewest active requests
+    fn least_connections(&self, candidates: &[usize]) -> usize {
+        let (active, weight) = candidates
+            .iter()
+            .map(|index| self.load(*index))
+            .min_by(|(a1, w1), (a2, w2)| (a1 * w2).cmp(&(a2 * w1)))
+            .expect("empty candidates");
+        let least: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|index| {
+                let (a, w) = self.load(*index);
+                a * weight == active * w
+            })
+            .collect();
+        self.round_robin(&least)
+    }
+
+    /// Pick two weighted random candidates and keep the least loaded
+    fn random_two_choices(&self, candidates: &[usize]) -> usize {
+        let first = self.weighted_random(candidates);
+        let rest: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|index| *index != first)
+            .collect();
+        if rest.is_empty() {
+            return first;
+        }
+        let second = self.weighted_random(&rest);
+        let ((a1, w1), (a2, w2)) = (self.load(first), self.load(second));
+        match a2 * w1 < a1 * w2 {
+            true => second,
+            false => first,
+        }
+    }
+
+    fn weighted_random(&self, candidates: &[usize]) -> usize {
+        let total: u64 = candidates
+            .iter()
+            .map(|index| self.upstreams[*index].weight as u64)
+            .sum();
+        let mut point = rand::random_range(0..total);
+        for index in candidates.iter().copied() {
+            let weight = self.upstreams[index].weight as u64;
+            if point < weight {
+                return index;
+            }
+            point -= weight;
+        }
+        candidates[candidates.len() - 1]
+    }
+
+    /// Walk the hash ring from the key to the first available candidate
+    fn consistent_hash(&self, key: &[u8], candidates: &[usize]) -> usize {
+        let point = hash(&key);
+        let start = self.ring.partition_point(|(p, _)| *p < point);
+        self.ring[start..]
+            .iter()
+            .chain(self.ring[..start].iter())
+            .map(|(_, index)| *index)
+            .find(|index| candidates.contains(index))
+            .unwrap_or(candidates[0])
+    }
+}
+
+#[inline]
+fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Active request assigned to an upstream of the pool
+pub(crate) struct Lease {
+    pool: Rc<Pool>,
+    index: usize,
+}
+
+impl Lease {
+    fn new(pool: Rc<Pool>, index: usize) -> Self {
+        let upstream = &pool.upstreams[index];
+        upstream.active.set(upstream.active.get() + 1);
+        Self { pool, index }
+    }
+
+    #[inline]
+    fn upstream(&self) -> &UpstreamState {
+        &self.pool.upstreams[self.index]
+    }
+
+    /// Index of the upstream within the pool
+    #[inline]
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Resolution uri of the upstream
+    #[inline]
+    pub(crate) fn uri(&self) -> &UpstreamUri {
+        &self.upstream().uri
+    }
+
+    /// Client requesting the upstream, if not the proxy's own client
+    #[inline]
+    pub(crate) fn client(&self) -> Option<&Client> {
+        self.upstream().client.as_ref()
+    }
+
+    /// Record a successful request to the upstream
+    pub(crate) fn success(&self) {
+        self.upstream().failures.set(0);
+    }
+
+    /// Record a failed request, ejecting the upstream after too many
+    /// consecutive failures
+    pub(crate) fn failure(&self) {
+        let Some(ejection) = self.pool.ejection else {
+            return;
+        };
+        let upstream = self.upstream();
+        let failures = upstream.failures.get() + 1;
+        if failures < ejection.max_failures {
+            upstream.failures.set(failures);
+            return;
+        }
+        tracing::warn!(
+            "ejecting upstream {} for {:?} after {failures} failures",
+            upstream.uri,
+            ejection.duration
+        );
+        upstream.failures.set(0);
+        upstream
+            .ejected
+            .set(Some(Instant::now() + ejection.duration));
+    }
+
+    /// Hold the lease until the response body is complete
+    pub(crate) fn attach(self, res: HttpResponse) -> HttpResponse {
+        res.map_body(|_, body| BoxBody::new(LeasedBody { body, _lease: self }))
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let upstream = self.upstream();
+        upstream.active.set(upstream.active.get().saturating_sub(1));
+    }
+}
+
+/// Response body which releases its upstream lease once dropped
+struct LeasedBody {
+    body: BoxBody,
+    _lease: Lease,
+}
+
+impl MessageBody for LeasedBody {
+    type Error = Box<dyn StdError>;
+
+    #[inline]
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    #[inline]
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Pin::new(&mut self.get_mut().body).poll_next(cx)
+    }
+}
+
+/// Spawn worker-local health checks for every upstream of the pool
+///
+/// The task ends once the pool is dropped.
+pub(crate) fn spawn_health_checks(pool: &Rc<Pool>, client: Rc<Client>, check: HealthCheck) {
+    let (path, query) = match check.path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (check.path.as_str(), None),
+    };
+    let targets: Vec<Option<Uri>> = pool
+        .upstreams
+        .iter()
+        .map(|upstream| combine_path(upstream.uri.uri(), path, query).ok())
+        .collect();
+    let pool: Weak<Pool> = Rc::downgrade(pool);
+    actix_web::rt::spawn(async move {
+        loop {
+            let Some(pool) = pool.upgrade() else {
+                break;
+            };
+            for (upstream, target) in pool.upstreams.iter().zip(targets.iter()) {
+                let Some(target) = target else {
+                    tracing::warn!("invalid health check path {:?}", check.path);
+                    continue;
+                };
+                let client = upstream.client.as_ref().unwrap_or(&*client);
+                let healthy = match client.get(target).timeout(check.timeout).send().await {
+                    Ok(res) => res.status().is_success() || res.status().is_redirection(),
+                    Err(err) => {
+                        tracing::debug!(
+                            "health check of upstream {} failed: {err:?}",
+                            upstream.uri
+                        );
+                        false
+                    }
+                };
+                if upstream.healthy.replace(healthy) != healthy {
+                    match healthy {
+                        true => tracing::info!("upstream {} is healthy", upstream.uri),
+                        false => tracing::warn!("upstream {} is unhealthy", upstream.uri),
+                    }
+                }
+            }
+            drop(pool);
+            sleep(check.interval).await;
+        }
+    });
+}
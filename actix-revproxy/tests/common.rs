@@ -1,8 +1,9 @@
-use std::sync::Once;
+use std::{net::TcpListener, sync::Once};
 
 use actix_web::{
+    App, Error, HttpServer,
     body::{self, BoxBody},
-    dev::ServiceResponse,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
 };
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
@@ -29,3 +30,33 @@ pub async fn get_body(res: ServiceResponse<BoxBody>) -> String {
         .expect("invalid body")
         .to_string()
 }
+
+/// Spawn an upstream server running the app built by `factory`
+/// and return its base uri.
+#[allow(dead_code)]
+pub fn spawn_upstream<F, T>(factory: F) -> String
+where
+    F: Fn() -> App<T> + Send + Clone + 'static,
+    T: ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse<BoxBody>,
+            Error = Error,
+            InitError = (),
+        > + 'static,
+{
+    let server = HttpServer::new(factory)
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .expect("failed to bind upstream");
+    let uri = format!("http://{}", server.addrs()[0]);
+    actix_web::rt::spawn(server.run());
+    uri
+}
+
+/// Reserve an address with nothing listening on it
+#[allow(dead_code)]
+pub fn unreachable() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+    format!("http://{}", listener.local_addr().unwrap())
+}
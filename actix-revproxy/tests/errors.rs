@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use actix_revproxy::{RevProxy, Upstream};
+use actix_web::{
+    App, HttpResponse,
+    http::StatusCode,
+    test::{self, TestRequest},
+    web,
+};
+
+mod common;
+
+/// Spawn an upstream server failing with a private error body on `/fail`
+/// and responding after a delay on `/slow`
+fn upstream() -> String {
+    common::spawn_upstream(|| {
+        App::new()
+            .route(
+                "/fail",
+                web::get().to(|| async { HttpResponse::InternalServerError().body("secret") }),
+            )
+            .route(
+                "/slow",
+                web::get().to(|| async {
+                    actix_web::rt::time::sleep(Duration::from_secs(2)).await;
+                    "slow"
+                }),
+            )
+            .default_service(web::to(|| async { "ok" }))
+    })
+}
+
+/// Call the proxy and collect the status of its error response
+async fn status(proxy: RevProxy, path: &str) -> StatusCode {
+    let srv = test::init_service(App::new().service(proxy)).await;
+    let req = TestRequest::with_uri(path).to_request();
+    let res = test::call_service(&srv, req).await;
+    assert!(res.response().error().is_some(), "missing response error");
+    res.status()
+}
+
+#[actix_web::test]
+async fn gateway_statuses() {
+    common::setup();
+
+    let proxy = RevProxy::new("", common::unreachable());
+    assert_eq!(status(proxy, "/").await, StatusCode::BAD_GATEWAY);
+
+    let client = awc::Client::builder()
+        .timeout(Duration::from_millis(200))
+        .finish();
+    let proxy = RevProxy::new("", upstream()).with_client(client);
+    assert_eq!(status(proxy, "/slow").await, StatusCode::GATEWAY_TIMEOUT);
+}
+
+#[actix_web::test]
+async fn no_healthy_upstream() {
+    common::setup();
+
+    let proxy = RevProxy::pool("", [Upstream::new(common::unreachable())])
+        .passive_health_check(1, Duration::from_secs(30));
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let req = TestRequest::with_uri("/").to_request();
+        statuses.push(test::call_service(&srv, req).await.status());
+    }
+    assert_eq!(
+        statuses,
+        [StatusCode::BAD_GATEWAY, StatusCode::SERVICE_UNAVAILABLE]
+    );
+}
+
+#[actix_web::test]
+async fn error_pages() {
+    common::setup();
+
+    let proxy = RevProxy::new("", common::unreachable())
+        .error_page(StatusCode::BAD_GATEWAY, "<h1>Be right back</h1>");
+    let srv = test::init_service(App::new().service(proxy)).await;
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(common::get_body(res).await, "<h1>Be right back</h1>");
+
+    let proxy = RevProxy::new("", common::unreachable())
+        .error_handler(|status, _| HttpResponse::build(status).body(status.to_string()));
+    let srv = test::init_service(App::new().service(proxy)).await;
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "502 Bad Gateway");
+}
+
+#[actix_web::test]
+async fn intercept_errors() {
+    common::setup();
+
+    let upstream = upstream();
+    let proxy = RevProxy::new("", upstream.as_str());
+    let srv = test::init_service(App::new().service(proxy)).await;
+    let req = TestRequest::with_uri("/fail").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "secret");
+
+    let proxy = RevProxy::new("", upstream.as_str()).intercept_errors();
+    let srv = test::init_service(App::new().service(proxy)).await;
+    let req = TestRequest::with_uri("/fail").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(common::get_body(res).await, "Internal Server Error");
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "ok");
+}
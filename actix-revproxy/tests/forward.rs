@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+
+use actix_revproxy::{Forwarding, RevProxy};
+use actix_web::{
+    App, HttpRequest, HttpResponse,
+    http::{StatusCode, header::HeaderValue},
+    test::{self, TestRequest},
+    web,
+};
+
+mod common;
+
+/// Spawn an upstream server responding with the received request headers
+fn upstream() -> String {
+    common::spawn_upstream(|| {
+        App::new().default_service(web::to(|req: HttpRequest| async move {
+            let headers: HashMap<String, String> = req
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_owned()))
+                .collect();
+            HttpResponse::Ok().json(headers)
+        }))
+    })
+}
+
+async fn forwarded_headers(proxy: RevProxy, peer: &str) -> HashMap<String, String> {
+    let srv = test::init_service(App::new().service(proxy)).await;
+    let req = TestRequest::with_uri("/app/path")
+        .peer_addr(peer.parse().unwrap())
+        .insert_header(("Host", "example.com"))
+        .insert_header(("X-Forwarded-For", "6.6.6.6"))
+        .insert_header(("X-Forwarded-Host", "evil.com"))
+        .insert_header(("X-Forwarded-Proto", "https"))
+        .insert_header(("Forwarded", "for=6.6.6.6"))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    serde_json::from_str(&common::get_body(res).await).expect("invalid json")
+}
+
+#[actix_web::test]
+async fn untrusted_peer() {
+    common::setup();
+
+    let proxy = RevProxy::new("/app", upstream())
+        .strip_prefix()
+        .forwarding(Forwarding::Both)
+        .trusted_proxy("10.0.0.0/8");
+    let headers = forwarded_headers(proxy, "192.168.1.5:4000").await;
+
+    assert_eq!(headers["host"], "example.com");
+    assert_eq!(headers["x-forwarded-for"], "192.168.1.5");
+    assert_eq!(headers["x-forwarded-host"], "example.com");
+    assert_eq!(headers["x-forwarded-proto"], "http");
+    assert_eq!(headers["x-forwarded-port"], "8080");
+    assert_eq!(headers["x-forwarded-prefix"], "/app");
+    assert_eq!(
+        headers["forwarded"],
+        "for=192.168.1.5;host=\"example.com\";proto=http"
+    );
+}
+
+#[actix_web::test]
+async fn trusted_peer() {
+    common::setup();
+
+    let proxy = RevProxy::new("/app", upstream())
+        .forwarding(Forwarding::Both)
+        .trusted_proxy("10.0.0.0/8");
+    let headers = forwarded_headers(proxy, "10.1.2.3:4000").await;
+
+    assert_eq!(headers["x-forwarded-for"], "6.6.6.6, 10.1.2.3");
+    assert_eq!(headers["x-forwarded-host"], "evil.com");
+    assert_eq!(headers["x-forwarded-proto"], "https");
+    assert!(!headers.contains_key("x-forwarded-prefix"));
+    assert_eq!(
+        headers["forwarded"],
+        "for=6.6.6.6, for=10.1.2.3;host=\"example.com\";proto=http"
+    );
+}
+
+#[actix_web::test]
+async fn malformed_headers() {
+    common::setup();
+
+    let proxy = RevProxy::new("/app", upstream())
+        .strip_prefix()
+        .forwarding(Forwarding::Both)
+        .trusted_proxy("10.0.0.0/8");
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let malformed = HeaderValue::from_bytes("caf\u{e9}".as_bytes()).unwrap();
+    let req = TestRequest::with_uri("/app/path")
+        .peer_addr("10.1.2.3:4000".parse().unwrap())
+        .insert_header(("Host", "example.com"))
+        .insert_header(("X-Forwarded-For", malformed.clone()))
+        .insert_header(("X-Forwarded-Prefix", malformed.clone()))
+        .insert_header(("Forwarded", malformed))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = common::get_body(res).await;
+    let headers: HashMap<String, String> = serde_json::from_str(&body).expect("invalid json");
+    assert_eq!(headers["x-forwarded-for"], "10.1.2.3");
+    assert_eq!(headers["x-forwarded-prefix"], "/app");
+    assert_eq!(
+        headers["forwarded"],
+        "for=10.1.2.3;host=\"example.com\";proto=http"
+    );
+}
+
+#[actix_web::test]
+async fn forwarding_modes() {
+    common::setup();
+
+    let upstream = upstream();
+    let proxy = RevProxy::new("/app", upstream.as_str()).forwarding(Forwarding::Standard);
+    let headers = forwarded_headers(proxy, "10.1.2.3:4000").await;
+    assert!(headers.contains_key("forwarded"));
+    assert!(!headers.contains_key("x-forwarded-for"));
+
+    let proxy = RevProxy::new("/app", upstream.as_str()).forwarding(Forwarding::None);
+    let headers = forwarded_headers(proxy, "10.1.2.3:4000").await;
+    assert!(!headers.keys().any(|name| name.contains("forwarded")));
+}
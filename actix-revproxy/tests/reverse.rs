@@ -0,0 +1,90 @@
+use actix_revproxy::RevProxy;
+use actix_web::{
+    App, HttpResponse,
+    http::header,
+    test::{self, TestRequest},
+    web,
+};
+
+mod common;
+
+/// Spawn an upstream server redirecting to its own internal address
+fn upstream() -> String {
+    common::spawn_upstream(|| {
+        App::new().default_service(web::to(|req: actix_web::HttpRequest| async move {
+            let base = format!("http://{}", req.app_config().local_addr());
+            HttpResponse::Found()
+                .insert_header((header::LOCATION, format!("{base}/login?next=%2F")))
+                .insert_header((header::CONTENT_LOCATION, format!("{base}/index")))
+                .insert_header((header::REFRESH, format!("5; url={base}/later")))
+                .append_header((header::SET_COOKIE, "sid=1; Domain=internal; Path=/"))
+                .append_header((header::SET_COOKIE, "lang=en; Domain=.other; Path=/api"))
+                .finish()
+        }))
+    })
+}
+
+fn header_values(res: &actix_web::dev::ServiceResponse, name: header::HeaderName) -> Vec<String> {
+    res.headers()
+        .get_all(name)
+        .map(|value| value.to_str().unwrap().to_owned())
+        .collect()
+}
+
+#[actix_web::test]
+async fn proxy_pass_reverse() {
+    common::setup();
+
+    let proxy = RevProxy::new("/app", upstream())
+        .strip_prefix()
+        .proxy_pass_reverse_upstreams()
+        .proxy_pass_reverse_cookie_domain("internal", "example.com")
+        .proxy_pass_reverse_cookie_path("/", "/app/");
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let req = TestRequest::with_uri("/app/")
+        .insert_header((header::HOST, "example.com"))
+        .insert_header((header::X_FORWARDED_HOST, "attacker.example"))
+        .insert_header((header::X_FORWARDED_PROTO, "https"))
+        .insert_header((header::FORWARDED, "host=attacker.example;proto=https"))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+
+    assert_eq!(
+        header_values(&res, header::LOCATION),
+        ["http://example.com/app/login?next=%2F"]
+    );
+    assert_eq!(
+        header_values(&res, header::CONTENT_LOCATION),
+        ["http://example.com/app/index"]
+    );
+    assert_eq!(
+        header_values(&res, header::REFRESH),
+        ["5; url=http://example.com/app/later"]
+    );
+    let mut cookies = header_values(&res, header::SET_COOKIE);
+    cookies.sort();
+    assert_eq!(
+        cookies,
+        [
+            "lang=en; Domain=.other; Path=/app/api",
+            "sid=1; Domain=example.com; Path=/app/",
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn reverse_disabled() {
+    common::setup();
+
+    let upstream = upstream();
+    let proxy = RevProxy::new("/app", upstream.as_str());
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let req = TestRequest::with_uri("/app/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(
+        header_values(&res, header::LOCATION),
+        [format!("{upstream}/login?next=%2F")]
+    );
+}
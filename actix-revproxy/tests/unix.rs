@@ -0,0 +1,122 @@
+#![cfg(unix)]
+
+use std::path::PathBuf;
+
+use actix_revproxy::{RevProxy, UpstreamUri};
+use actix_web::{
+    App, HttpRequest, HttpResponse, HttpServer,
+    http::{Uri, header},
+    test::{self, TestRequest},
+    web,
+};
+
+mod common;
+
+/// Spawn an upstream server on a unix socket responding with the received
+/// host header and request uri, or redirecting `/redirect` to `/login` on
+/// the received host
+fn upstream(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("revproxy-{name}-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let server = HttpServer::new(|| {
+        App::new().default_service(web::to(|req: HttpRequest| async move {
+            let host = req.headers().get(header::HOST).unwrap().to_str().unwrap();
+            if req.path() == "/redirect" {
+                return HttpResponse::Found()
+                    .insert_header((header::LOCATION, format!("http://{host}/login")))
+                    .finish();
+            }
+            HttpResponse::Ok().body(format!("{host} {}", req.uri()))
+        }))
+    })
+    .workers(1)
+    .bind_uds(&path)
+    .expect("failed to bind upstream");
+    actix_web::rt::spawn(server.run());
+    path
+}
+
+#[actix_web::test]
+async fn unix_socket() {
+    common::setup();
+
+    let socket = upstream("keep");
+    let proxy = RevProxy::new("/app", format!("unix://{}", socket.display()));
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let req = TestRequest::with_uri("/app/a%2Fb?x=1&x=2")
+        .insert_header((header::HOST, "example.com"))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(
+        common::get_body(res).await,
+        "example.com /app/a%2Fb?x=1&x=2"
+    );
+}
+
+#[actix_web::test]
+async fn unix_socket_change_host() {
+    common::setup();
+
+    let socket = upstream("change");
+    let proxy = RevProxy::new("/app", format!("unix://{}", socket.display()))
+        .strip_prefix()
+        .change_host();
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let req = TestRequest::with_uri("/app/index")
+        .insert_header((header::HOST, "example.com"))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "example.com /index");
+}
+
+#[actix_web::test]
+async fn unix_socket_reverse() {
+    common::setup();
+
+    let socket = upstream("reverse");
+    let proxy = RevProxy::new("/app", format!("unix://{}", socket.display()))
+        .strip_prefix()
+        .proxy_pass_reverse_upstreams();
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let req = TestRequest::with_uri("/app/redirect")
+        .insert_header((header::HOST, "example.com"))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(
+        res.headers().get(header::LOCATION).unwrap(),
+        "http://example.com/app/login"
+    );
+}
+
+#[actix_web::test]
+async fn unix_socket_long_path() {
+    common::setup();
+
+    let socket = upstream("with-a-name-longer-than-a-single-dns-label-may-be");
+    let proxy = RevProxy::new("/", format!("unix://{}", socket.display()));
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let req = TestRequest::with_uri("/index")
+        .insert_header((header::HOST, "example.com"))
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(common::get_body(res).await, "example.com /index");
+}
+
+#[test]
+fn unix_socket_display() {
+    let uri = UpstreamUri::try_from("unix:///run/app.sock").unwrap();
+    assert_eq!(uri.to_string(), "unix:/run/app.sock");
+
+    let uri = UpstreamUri::try_from(b"unix:///run/app.sock".to_vec()).unwrap();
+    assert_eq!(uri.to_string(), "unix:/run/app.sock");
+
+    let parts = Uri::from_static("http://127.0.0.1:8080/api").into_parts();
+    let uri = UpstreamUri::try_from(parts).unwrap();
+    assert_eq!(uri.to_string(), "http://127.0.0.1:8080/api");
+
+    assert!(UpstreamUri::try_from("unix://").is_err());
+}
@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use actix_revproxy::{HealthCheck, RevProxy, Upstream};
+use actix_web::{
+    App, HttpResponse,
+    http::{Method, StatusCode},
+    test::{self, TestRequest},
+    web,
+};
+
+mod common;
+
+/// Spawn an upstream server responding with its name, and with the
+/// given status on `/health`
+fn upstream(name: &'static str, health: StatusCode) -> String {
+    common::spawn_upstream(move || {
+        App::new()
+            .route(
+                "/health",
+                web::get().to(move || async move { HttpResponse::new(health) }),
+            )
+            .default_service(web::to(move || async move { name }))
+    })
+}
+
+/// Spawn an upstream server failing every request
+fn failing() -> String {
+    common::spawn_upstream(|| {
+        App::new().default_service(web::to(|| async {
+            HttpResponse::InternalServerError().finish()
+        }))
+    })
+}
+
+/// Spawn an upstream server mounted below `/svc` responding with its
+/// name, which is only healthy on `/svc/health`
+fn mounted(name: &'static str) -> String {
+    let uri = common::spawn_upstream(move || {
+        App::new()
+            .route(
+                "/svc/health",
+                web::get().to(|| async { HttpResponse::Ok().finish() }),
+            )
+            .route(
+                "/health",
+                web::get().to(|| async { HttpResponse::ServiceUnavailable().finish() }),
+            )
+            .default_service(web::to(move || async move { name }))
+    });
+    format!("{uri}/svc")
+}
+
+#[actix_web::test]
+async fn round_robin() {
+    common::setup();
+
+    let proxy = RevProxy::pool(
+        "",
+        [
+            Upstream::new(upstream("a", StatusCode::OK)),
+            Upstream::new(upstream("b", StatusCode::OK)).weight(2),
+        ],
+    );
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let mut bodies = Vec::new();
+    for _ in 0..6 {
+        let req = TestRequest::with_uri("/").to_request();
+        let res = test::call_service(&srv, req).await;
+        bodies.push(common::get_body(res).await);
+    }
+    bodies.sort();
+    assert_eq!(bodies, ["a", "a", "b", "b", "b", "b"]);
+}
+
+#[actix_web::test]
+async fn failover_idempotent() {
+    common::setup();
+
+    let proxy = RevProxy::pool(
+        "",
+        [
+            Upstream::new(common::unreachable()),
+            Upstream::new(upstream("b", StatusCode::OK)),
+        ],
+    );
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    for _ in 0..4 {
+        let req = TestRequest::with_uri("/").to_request();
+        let res = test::call_service(&srv, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(common::get_body(res).await, "b");
+    }
+
+    let req = TestRequest::with_uri("/")
+        .method(Method::POST)
+        .set_payload("data")
+        .to_request();
+    let res = test::call_service(&srv, req).await;
+    let req = TestRequest::with_uri("/")
+        .method(Method::POST)
+        .set_payload("data")
+        .to_request();
+    let res2 = test::call_service(&srv, req).await;
+    assert!(
+        res.status() == StatusCode::BAD_GATEWAY || res2.status() == StatusCode::BAD_GATEWAY,
+        "post must not be retried"
+    );
+}
+
+#[actix_web::test]
+async fn passive_ejection() {
+    common::setup();
+
+    let proxy = RevProxy::pool(
+        "",
+        [
+            Upstream::new(common::unreachable()),
+            Upstream::new(upstream("b", StatusCode::OK)),
+        ],
+    )
+    .max_retries(0)
+    .passive_health_check(1, Duration::from_secs(60));
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    for _ in 0..4 {
+        let req = TestRequest::with_uri("/").to_request();
+        let res = test::call_service(&srv, req).await;
+        assert_eq!(common::get_body(res).await, "b");
+    }
+}
+
+#[actix_web::test]
+async fn passive_ejection_server_error() {
+    common::setup();
+
+    let proxy = RevProxy::pool(
+        "",
+        [
+            Upstream::new(failing()),
+            Upstream::new(upstream("b", StatusCode::OK)),
+        ],
+    )
+    .passive_health_check(1, Duration::from_secs(60));
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    let req = TestRequest::with_uri("/").to_request();
+    let res = test::call_service(&srv, req).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    for _ in 0..4 {
+        let req = TestRequest::with_uri("/").to_request();
+        let res = test::call_service(&srv, req).await;
+        assert_eq!(common::get_body(res).await, "b");
+    }
+}
+
+#[actix_web::test]
+async fn health_check_base_path() {
+    common::setup();
+
+    let check = HealthCheck::new("/health").interval(Duration::from_millis(50));
+    let proxy = RevProxy::pool(
+        "",
+        [
+            Upstream::new(mounted("a")),
+            Upstream::new(upstream("b", StatusCode::OK)),
+        ],
+    )
+    .health_check(check);
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+    let mut bodies = Vec::new();
+    for _ in 0..4 {
+        let req = TestRequest::with_uri("/").to_request();
+        let res = test::call_service(&srv, req).await;
+        bodies.push(common::get_body(res).await);
+    }
+    bodies.sort();
+    assert_eq!(bodies, ["a", "a", "b", "b"]);
+}
+
+#[actix_web::test]
+async fn active_health_check() {
+    common::setup();
+
+    let check = HealthCheck::new("/health").interval(Duration::from_millis(50));
+    let proxy = RevProxy::pool(
+        "",
+        [
+            Upstream::new(upstream("a", StatusCode::SERVICE_UNAVAILABLE)),
+            Upstream::new(upstream("b", StatusCode::OK)),
+        ],
+    )
+    .health_check(check);
+    let srv = test::init_service(App::new().service(proxy)).await;
+
+    actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+    for _ in 0..4 {
+        let req = TestRequest::with_uri("/").to_request();
+        let res = test::call_service(&srv, req).await;
+        assert_eq!(common::get_body(res).await, "b");
+    }
+}
@@ -0,0 +1,79 @@
+use actix_revproxy::{RevProxy, proxy::combine_uri};
+use actix_web::{
+    App, HttpRequest,
+    http::Uri,
+    test::{self, TestRequest},
+    web,
+};
+
+mod common;
+
+fn combine(proxy: &'static str, target: &'static str) -> String {
+    let proxy = Uri::from_static(proxy);
+    let target = Uri::from_static(target);
+    combine_uri(&proxy, &target)
+        .expect("failed to combine uri")
+        .to_string()
+}
+
+#[test]
+fn combine_paths() {
+    assert_eq!(combine("http://up", "/foo"), "http://up/foo");
+    assert_eq!(combine("http://up/", "/foo"), "http://up/foo");
+    assert_eq!(combine("http://up/api", "/foo"), "http://up/api/foo");
+    assert_eq!(combine("http://up/api/", "/foo/"), "http://up/api/foo/");
+    assert_eq!(combine("http://up/api", "/"), "http://up/api/");
+    assert_eq!(
+        combine("http://up/api", "/a//b/../%2e%2E/c%2Fd"),
+        "http://up/api/a//b/../%2e%2E/c%2Fd"
+    );
+}
+
+#[test]
+fn combine_queries() {
+    assert_eq!(combine("http://up", "/?a=1&a=2"), "http://up/?a=1&a=2");
+    assert_eq!(
+        combine("http://up?key=v", "/x?b=2&a=1&b=3"),
+        "http://up/x?key=v&b=2&a=1&b=3"
+    );
+    assert_eq!(
+        combine("http://up/api", "/x?q=a%20b+c&e=%3D&flag"),
+        "http://up/api/x?q=a%20b+c&e=%3D&flag"
+    );
+    assert_eq!(combine("http://up?key=v", "/x"), "http://up/x?key=v");
+    assert_eq!(combine("http://up", "/x?"), "http://up/x");
+}
+
+/// Spawn an upstream server responding with the received request uri
+fn upstream() -> String {
+    common::spawn_upstream(|| {
+        App::new().default_service(web::to(
+            |req: HttpRequest| async move { req.uri().to_string() },
+        ))
+    })
+}
+
+#[actix_web::test]
+async fn mount_prefix() {
+    common::setup();
+
+    let upstream = format!("{}/base", upstream());
+    let app = App::new()
+        .service(RevProxy::new("/keep", upstream.as_str()))
+        .service(
+            web::scope("/v1").service(RevProxy::new("/strip", upstream.as_str()).strip_prefix()),
+        );
+    let srv = test::init_service(app).await;
+
+    let cases = [
+        ("/keep/a%2Fb?x=1&x=2", "/base/keep/a%2Fb?x=1&x=2"),
+        ("/v1/strip/a%2Fb/%41?x=1&x=2", "/base/a%2Fb/%41?x=1&x=2"),
+        ("/v1/strip", "/base"),
+        ("/v1/strip/", "/base/"),
+    ];
+    for (path, expected) in cases {
+        let req = TestRequest::with_uri(path).to_request();
+        let res = test::call_service(&srv, req).await;
+        assert_eq!(common::get_body(res).await, expected, "{path}");
+    }
+}
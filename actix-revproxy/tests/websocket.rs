@@ -0,0 +1,230 @@
+use std::{
+    convert::Infallible,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws;
+use actix_revproxy::RevProxy;
+use actix_web::{
+    App, Error, HttpRequest, HttpResponse, HttpServer,
+    body::{BodyStream, BoxBody},
+    dev::ServerHandle,
+    web::{self, Bytes, BytesMut},
+};
+use futures_util::{SinkExt, StreamExt, stream};
+
+mod common;
+
+/// Minimal WebSocket echo handler built on the actix-http codec
+async fn echo(req: HttpRequest, payload: web::Payload) -> Result<HttpResponse, Error> {
+    let mut res = ws::handshake(req.head())?;
+    let state = (payload, ws::Codec::new(), BytesMut::new(), false);
+    let frames = stream::unfold(
+        state,
+        |(mut payload, mut codec, mut buf, done)| async move {
+            if done {
+                return None;
+            }
+            let frame = loop {
+                match codec.decode(&mut buf).ok()? {
+                    Some(frame) => break frame,
+                    None => buf.extend_from_slice(&payload.next().await?.ok()?),
+                }
+            };
+            let (message, done) = match frame {
+                ws::Frame::Text(text) => (ws::Message::Text(text.try_into().ok()?), false),
+                ws::Frame::Binary(data) => (ws::Message::Binary(data), false),
+                ws::Frame::Ping(data) => (ws::Message::Pong(data), false),
+                ws::Frame::Close(reason) => (ws::Message::Close(reason), true),
+                _ => return None,
+            };
+            let mut out = BytesMut::new();
+            codec.encode(message, &mut out).ok()?;
+            Some((
+                Ok::<_, Infallible>(out.freeze()),
+                (payload, codec, buf, done),
+            ))
+        },
+    );
+    Ok(res
+        .message_body(BoxBody::new(BodyStream::new(frames)))?
+        .into())
+}
+
+/// Start an echo server behind a reverse proxy and return the proxy address
+fn serve<F>(configure: F) -> (SocketAddr, ServerHandle, ServerHandle)
+where
+    F: Fn(RevProxy) -> RevProxy + Send + Clone + 'static,
+{
+    let server = HttpServer::new(|| App::new().route("/ws", web::get().to(echo)))
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .expect("failed to bind upstream");
+    let upstream = format!("http://{}", server.addrs()[0]);
+    let server = server.run();
+    let upstream_handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let server = HttpServer::new(move || {
+        let proxy = configure(RevProxy::new("/", upstream.as_str()));
+        App::new().service(proxy)
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .expect("failed to bind proxy");
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    (addr, handle, upstream_handle)
+}
+
+#[actix_web::test]
+async fn websocket_echo() {
+    common::setup();
+
+    let (addr, handle, upstream) = serve(|proxy| proxy);
+    let (res, mut conn) = awc::Client::new()
+        .ws(format!("ws://{addr}/ws"))
+        .connect()
+        .await
+        .expect("failed to connect");
+    assert_eq!(res.status().as_u16(), 101);
+
+    conn.send(ws::Message::Text("hello world".into()))
+        .await
+        .unwrap();
+    let frame = conn.next().await.expect("missing frame").unwrap();
+    assert_eq!(frame, ws::Frame::Text(Bytes::from_static(b"hello world")));
+
+    conn.send(ws::Message::Binary(Bytes::from_static(&[1, 2, 3])))
+        .await
+        .unwrap();
+    let frame = conn.next().await.expect("missing frame").unwrap();
+    assert_eq!(frame, ws::Frame::Binary(Bytes::from_static(&[1, 2, 3])));
+
+    conn.send(ws::Message::Close(None)).await.unwrap();
+    let frame = conn.next().await.expect("missing frame").unwrap();
+    assert_eq!(frame, ws::Frame::Close(None));
+
+    handle.stop(false).await;
+    upstream.stop(false).await;
+}
+
+#[actix_web::test]
+async fn websocket_message_limit() {
+    common::setup();
+
+    let (addr, handle, upstream) = serve(|proxy| proxy.websocket_max_message_size(16));
+    let (_, mut conn) = awc::Client::new()
+        .ws(format!("ws://{addr}/ws"))
+        .connect()
+        .await
+        .expect("failed to connect");
+
+    conn.send(ws::Message::Text("small".into())).await.unwrap();
+    let frame = conn.next().await.expect("missing frame").unwrap();
+    assert_eq!(frame, ws::Frame::Text(Bytes::from_static(b"small")));
+
+    let large = "x".repeat(64);
+    conn.send(ws::Message::Text(large.into())).await.unwrap();
+    let frame = conn.next().await.expect("missing frame").unwrap();
+    assert_eq!(frame, ws::Frame::Close(Some(ws::CloseCode::Size.into())));
+    assert!(matches!(conn.next().await, None | Some(Err(_))));
+
+    handle.stop(false).await;
+    upstream.stop(false).await;
+}
+
+#[actix_web::test]
+async fn websocket_idle_timeout() {
+    common::setup();
+
+    let timeout = Duration::from_millis(200);
+    let (addr, handle, upstream) = serve(move |proxy| proxy.websocket_idle_timeout(timeout));
+    let (_, mut conn) = awc::Client::new()
+        .ws(format!("ws://{addr}/ws"))
+        .connect()
+        .await
+        .expect("failed to connect");
+
+    // activity within the timeout keeps the connection open
+    for _ in 0..3 {
+        actix_web::rt::time::sleep(timeout / 2).await;
+        conn.send(ws::Message::Ping(Bytes::new())).await.unwrap();
+        let frame = conn.next().await.expect("missing frame").unwrap();
+        assert_eq!(frame, ws::Frame::Pong(Bytes::new()));
+    }
+
+    let next = actix_web::rt::time::timeout(timeout * 5, conn.next())
+        .await
+        .expect("idle connection was not closed");
+    assert!(matches!(next, None | Some(Err(_))));
+
+    handle.stop(false).await;
+    upstream.stop(false).await;
+}
+
+#[actix_web::test]
+async fn websocket_idle_timeout_releases_upstream() {
+    common::setup();
+
+    // raw upstream reporting whether the proxy closed its connection
+    let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind upstream");
+    let upstream = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut io, _) = listener.accept().expect("failed to accept");
+        let mut buf = [0u8; 4096];
+        let n = io.read(&mut buf).expect("failed to read handshake");
+        let head = String::from_utf8_lossy(&buf[..n]);
+        let key = head
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("sec-websocket-key"))
+            .map(|(_, key)| key)
+            .expect("missing websocket key");
+        let accept = ws::hash_key(key.trim().as_bytes());
+        write!(
+            io,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            String::from_utf8_lossy(&accept)
+        )
+        .expect("failed to respond");
+        io.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let _ = tx.send(matches!(io.read(&mut buf), Ok(0)));
+    });
+
+    let timeout = Duration::from_millis(200);
+    let server = HttpServer::new(move || {
+        App::new().service(RevProxy::new("/", upstream.as_str()).websocket_idle_timeout(timeout))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .expect("failed to bind proxy");
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let (_, _conn) = awc::Client::new()
+        .ws(format!("ws://{addr}/ws"))
+        .connect()
+        .await
+        .expect("failed to connect");
+
+    // the client stays connected, so only the proxy may close the upstream
+    actix_web::rt::time::sleep(Duration::from_secs(3)).await;
+    assert_eq!(
+        rx.try_recv(),
+        Ok(true),
+        "upstream connection was not closed"
+    );
+
+    handle.stop(false).await;
+}